@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use biomass_rs::{
+	bodies::Asteroid,
+	config::GameConfig,
+	constants::{ASTEROID_RADIUS, ASTEROID_SPAWN_DISTANCE, PLANET_RADIUS},
+	level::{Level, Levels},
+	physics::{BodyState, SimulationClock},
+	simulation::SimulationPlugin,
+	slingshot::pick_asteroid,
+	spatial::{GridEntry, SpatialGrid},
+	stress::StressPlugin,
+};
+use rand::{prelude::*, rngs::StdRng};
+
+/// Numbers of asteroids to measure with.
+const ASTEROIDS: [usize; 4] = [500, 1000, 2000, 5000];
+/// Fixed steps measured for each number of asteroids.
+const STEPS: u32 = 300;
+/// Pointers looking for an asteroid to grab on every step, far more than there are fingers.
+const PICKS: usize = 10;
+/// Mass of the asteroids when measuring them pulling each other.
+const ASTEROID_MASS: f32 = 0.01;
+/// Time a whole frame can take at 60 FPS.
+const FRAME_BUDGET: Duration = Duration::from_micros(16_667);
+
+/// Asteroids on circular orbits around the center of the level, clear of the planet and within
+/// the screen, the way the stress mode spawns them.
+fn orbiting_entries(
+	config: &GameConfig,
+	level: &Level,
+	rng: &mut StdRng,
+	count: usize,
+) -> Vec<GridEntry> {
+	// As if all planets were one at the center
+	let mass: f32 = level.planets.iter().map(|planet| planet.mass()).sum();
+	(0..count)
+		.map(|id| {
+			let distance = rng.gen_range(200.0..ASTEROID_SPAWN_DISTANCE);
+			let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+			let direction = Vec2::new(angle.cos(), angle.sin());
+			GridEntry {
+				entity: Entity::new(id as u32),
+				body: BodyState {
+					position: distance * direction,
+					velocity: (config.gravity * mass / distance).sqrt() * direction.perp(),
+				},
+				radius: ASTEROID_RADIUS,
+			}
+		})
+		.collect()
+}
+
+fn milliseconds_per_step(total: Duration) -> f64 {
+	total.as_secs_f64() * 1000. / STEPS as f64
+}
+
+/// Times rebuilding the grid, finding the asteroids colliding with each other and with the
+/// planet, and picking asteroids, as done on every fixed step.
+fn measure_grid(config: &GameConfig, level: &Level, count: usize) {
+	let mut rng = StdRng::seed_from_u64(count as u64);
+	let entries = orbiting_entries(config, level, &mut rng, count);
+	let pointers: Vec<Vec2> = (0..PICKS)
+		.map(|_| entries[rng.gen_range(0..count)].body.position)
+		.collect();
+
+	let mut grid = SpatialGrid::default();
+	let mut rebuild = Duration::default();
+	let mut collisions = Duration::default();
+	let mut picking = Duration::default();
+	// Counted so that none of the work can be optimized away
+	let (mut pairs, mut crashes, mut picked) = (0, 0, 0);
+	for _ in 0..STEPS {
+		let start = Instant::now();
+		grid.rebuild(entries.iter().copied());
+		rebuild += start.elapsed();
+
+		let start = Instant::now();
+		pairs += grid.overlapping_pairs().len();
+		crashes += grid.query_circle(Vec2::zero(), PLANET_RADIUS).len();
+		collisions += start.elapsed();
+
+		let start = Instant::now();
+		picked += pointers
+			.iter()
+			.filter(|pointer| pick_asteroid(config, &grid, **pointer, &[]).is_some())
+			.count();
+		picking += start.elapsed();
+	}
+
+	println!(
+		"{:>5} asteroids, spatial grid: {:.3} ms/step (rebuild {:.3}, collisions {:.3}, {} picks \
+		 {:.3}); {} pairs, {} crashes, {} picked",
+		count,
+		milliseconds_per_step(rebuild + collisions + picking),
+		milliseconds_per_step(rebuild),
+		milliseconds_per_step(collisions),
+		PICKS,
+		milliseconds_per_step(picking),
+		pairs / STEPS as usize,
+		crashes / STEPS as usize,
+		picked / STEPS as usize,
+	);
+}
+
+fn step(app: &mut App) {
+	app.resources
+		.get_mut::<SimulationClock>()
+		.unwrap()
+		.requested_steps = 1;
+	app.update();
+}
+
+/// Times whole headless frames of the stress mode, with one fixed step each, with asteroids of the
+/// given mass.
+fn measure_simulation(count: usize, asteroid_mass: f32) {
+	let mut builder = App::build();
+	builder
+		.add_plugins(MinimalPlugins)
+		.add_plugin(SimulationPlugin {
+			seed: Some(count as u64),
+			..Default::default()
+		})
+		.add_plugin(StressPlugin { asteroids: count });
+	let mut app = builder.app;
+	app.resources.get_mut::<SimulationClock>().unwrap().stepping = true;
+	app.resources.get_mut::<GameConfig>().unwrap().asteroid_mass = asteroid_mass;
+
+	// Spawns the asteroids
+	step(&mut app);
+
+	let start = Instant::now();
+	for _ in 0..STEPS {
+		step(&mut app);
+	}
+	let frame = start.elapsed() / STEPS;
+
+	let left = app.world.query::<&Asteroid>().count();
+	println!(
+		"{:>5} asteroids of mass {}, whole frame: {:.3} ms, {} ({} asteroids left)",
+		count,
+		asteroid_mass,
+		frame.as_secs_f64() * 1000.,
+		if frame <= FRAME_BUDGET {
+			"holds 60 FPS"
+		} else {
+			"too slow for 60 FPS"
+		},
+		left,
+	);
+}
+
+fn main() {
+	// The config and the level the stress mode starts with, as the game loads them
+	let mut builder = App::build();
+	builder
+		.add_plugins(MinimalPlugins)
+		.add_plugin(SimulationPlugin::default());
+	{
+		let resources = builder.resources();
+		let config = resources.get::<GameConfig>().unwrap();
+		let levels = resources.get::<Levels>().unwrap();
+		for count in ASTEROIDS.iter() {
+			measure_grid(&config, levels.current(), *count);
+		}
+	}
+	for &asteroid_mass in [0., ASTEROID_MASS].iter() {
+		for count in ASTEROIDS.iter() {
+			measure_simulation(*count, asteroid_mass);
+		}
+	}
+}
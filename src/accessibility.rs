@@ -0,0 +1,316 @@
+use std::fmt;
+
+use bevy::prelude::*;
+
+use crate::{
+	bodies::Asteroid,
+	constants::{ASTEROID_RADIUS, N_ASTEROIDS},
+	presentation::INTERPOLATION,
+};
+
+/// Side of the badge drawn over asteroids, relative to the width of their sprite.
+pub const BADGE_SIZE: f32 = 0.5;
+/// Side of the pips on badges, and distance between them, relative to the width of the sprite.
+pub const PIP_SIZE: f32 = 0.1;
+pub const PIP_SPACING: f32 = 0.13;
+
+pub type Rgb = (f32, f32, f32);
+
+/// Colors of the badges of each kind of asteroid, chosen to be told apart with a deficiency.
+const DEUTERANOPIA_PALETTE: [Rgb; N_ASTEROIDS] = [
+	(0.0, 0.0, 0.0),
+	(0.9, 0.62, 0.0),
+	(0.34, 0.71, 0.91),
+	(0.0, 0.62, 0.45),
+	(0.94, 0.89, 0.26),
+	(0.0, 0.45, 0.7),
+	(0.84, 0.37, 0.0),
+	(0.8, 0.47, 0.65),
+	(1.0, 1.0, 1.0),
+];
+const PROTANOPIA_PALETTE: [Rgb; N_ASTEROIDS] = [
+	(0.0, 0.0, 0.0),
+	(0.0, 0.45, 0.7),
+	(0.34, 0.71, 0.91),
+	(0.6, 0.5, 0.0),
+	(0.94, 0.89, 0.26),
+	(0.35, 0.35, 0.35),
+	(0.58, 0.55, 0.9),
+	(0.75, 0.75, 0.75),
+	(1.0, 1.0, 1.0),
+];
+const TRITANOPIA_PALETTE: [Rgb; N_ASTEROIDS] = [
+	(0.0, 0.0, 0.0),
+	(0.6, 0.0, 0.0),
+	(0.9, 0.2, 0.2),
+	(1.0, 0.6, 0.7),
+	(0.0, 0.5, 0.5),
+	(0.4, 0.85, 0.85),
+	(0.5, 0.5, 0.5),
+	(0.8, 0.8, 0.8),
+	(1.0, 1.0, 1.0),
+];
+
+/// Pips on the badge of each kind of asteroid, laid out like the faces of a die on a 3×3 grid,
+/// from -1 to 1 with y pointing up.
+const PIPS: [&[(f32, f32)]; N_ASTEROIDS] = [
+	&[(0., 0.)],
+	&[(-1., 1.), (1., -1.)],
+	&[(-1., 1.), (0., 0.), (1., -1.)],
+	&[(-1., 1.), (1., 1.), (-1., -1.), (1., -1.)],
+	&[(-1., 1.), (1., 1.), (0., 0.), (-1., -1.), (1., -1.)],
+	&[
+		(-1., 1.),
+		(1., 1.),
+		(-1., 0.),
+		(1., 0.),
+		(-1., -1.),
+		(1., -1.),
+	],
+	&[
+		(-1., 1.),
+		(1., 1.),
+		(-1., 0.),
+		(0., 0.),
+		(1., 0.),
+		(-1., -1.),
+		(1., -1.),
+	],
+	&[
+		(-1., 1.),
+		(0., 1.),
+		(1., 1.),
+		(-1., 0.),
+		(1., 0.),
+		(-1., -1.),
+		(0., -1.),
+		(1., -1.),
+	],
+	&[
+		(-1., 1.),
+		(0., 1.),
+		(1., 1.),
+		(-1., 0.),
+		(0., 0.),
+		(1., 0.),
+		(-1., -1.),
+		(0., -1.),
+		(1., -1.),
+	],
+];
+
+/// How asteroids are told apart: by the colors of their sprites, or by badges with pips in colors
+/// suited to a color vision deficiency.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorblindMode {
+	#[default]
+	Off,
+	Deuteranopia,
+	Protanopia,
+	Tritanopia,
+}
+
+impl fmt::Display for ColorblindMode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let name = match self {
+			ColorblindMode::Off => "off",
+			ColorblindMode::Deuteranopia => "deuteranopia",
+			ColorblindMode::Protanopia => "protanopia",
+			ColorblindMode::Tritanopia => "tritanopia",
+		};
+		write!(f, "{}", name)
+	}
+}
+
+impl ColorblindMode {
+	pub const ALL: [ColorblindMode; 4] = [
+		ColorblindMode::Off,
+		ColorblindMode::Deuteranopia,
+		ColorblindMode::Protanopia,
+		ColorblindMode::Tritanopia,
+	];
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL
+			.iter()
+			.copied()
+			.find(|mode| mode.to_string() == name)
+	}
+
+	/// The mode after this one, going back to the first after the last.
+	pub fn next(self) -> Self {
+		let index = Self::ALL.iter().position(|mode| *mode == self).unwrap();
+		Self::ALL[(index + 1) % Self::ALL.len()]
+	}
+
+	/// Colors of the badges of each kind of asteroid, if they have any.
+	pub fn palette(self) -> Option<&'static [Rgb; N_ASTEROIDS]> {
+		match self {
+			ColorblindMode::Off => None,
+			ColorblindMode::Deuteranopia => Some(&DEUTERANOPIA_PALETTE),
+			ColorblindMode::Protanopia => Some(&PROTANOPIA_PALETTE),
+			ColorblindMode::Tritanopia => Some(&TRITANOPIA_PALETTE),
+		}
+	}
+}
+
+/// Pips on the badge of a kind of asteroid.
+pub fn pips(kind: usize) -> &'static [(f32, f32)] {
+	PIPS[kind]
+}
+
+/// Whether a badge is dark enough for white pips to stand out from it.
+fn is_dark(badge: Rgb) -> bool {
+	0.2126 * badge.0 + 0.7152 * badge.1 + 0.0722 * badge.2 < 0.5
+}
+
+/// Color of the pips on a badge, so that they stand out from it.
+pub fn pip_color(badge: Rgb) -> Rgb {
+	if is_dark(badge) {
+		(1., 1., 1.)
+	} else {
+		(0., 0., 0.)
+	}
+}
+
+/// Draws badges over asteroids in colorblind modes, and lets the player switch between modes.
+pub struct AccessibilityPlugin {
+	pub colorblind_mode: ColorblindMode,
+}
+
+impl Plugin for AccessibilityPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_resource(self.colorblind_mode)
+			.add_system(attach_glyphs.system())
+			// Badges follow asteroids where they are drawn rather than where they are
+			.add_system_to_stage(INTERPOLATION, place_glyphs.system());
+	}
+}
+
+/// Part of the badge of an asteroid: its background or one of its pips.
+///
+/// Not a child of the asteroid, so that it doesn't spin along with it.
+pub struct Glyph {
+	asteroid: Entity,
+	/// From the center of the asteroid, at its default size.
+	offset: Vec3,
+}
+
+/// Materials of the badges of each kind of asteroid in the current mode, and of their pips, shared
+/// by every glyph.
+#[derive(Default)]
+pub struct GlyphMaterials {
+	badges: Vec<Handle<ColorMaterial>>,
+	pips: Vec<Handle<ColorMaterial>>,
+}
+
+impl GlyphMaterials {
+	fn new(materials: &mut Assets<ColorMaterial>, palette: &[Rgb]) -> Self {
+		let black = materials.add(Color::BLACK.into());
+		let white = materials.add(Color::WHITE.into());
+		let badges = palette
+			.iter()
+			.map(|&(r, g, b)| materials.add(Color::rgb(r, g, b).into()))
+			.collect();
+		let pips = palette
+			.iter()
+			.map(|badge| {
+				if is_dark(*badge) {
+					white.clone()
+				} else {
+					black.clone()
+				}
+			})
+			.collect();
+		Self { badges, pips }
+	}
+}
+
+fn spawn_glyph(
+	commands: &mut Commands,
+	material: Handle<ColorMaterial>,
+	asteroid: Entity,
+	offset: Vec3,
+	size: f32,
+) {
+	commands
+		.spawn(SpriteBundle {
+			material,
+			sprite: Sprite::new(Vec2::splat(size)),
+			..Default::default()
+		})
+		.with(Glyph { asteroid, offset });
+}
+
+/// Spawns badges for new asteroids, and for every asteroid again when the mode changes.
+#[allow(clippy::too_many_arguments)]
+pub fn attach_glyphs(
+	commands: &mut Commands,
+	mode: Res<ColorblindMode>,
+	mut previous_mode: Local<ColorblindMode>,
+	mut glyph_materials: Local<GlyphMaterials>,
+	mut materials: ResMut<Assets<ColorMaterial>>,
+	asteroids: Query<(Entity, &Asteroid)>,
+	new_asteroids: Query<(Entity, &Asteroid), Added<Asteroid>>,
+	glyphs: Query<Entity, With<Glyph>>,
+) {
+	let changed = *previous_mode != *mode;
+	if changed {
+		*previous_mode = *mode;
+		for glyph in glyphs.iter() {
+			commands.despawn(glyph);
+		}
+	}
+
+	let palette = match mode.palette() {
+		Some(palette) => palette,
+		None => return,
+	};
+	if changed {
+		*glyph_materials = GlyphMaterials::new(&mut materials, palette);
+	}
+	let asteroids: Vec<(Entity, &Asteroid)> = if changed {
+		asteroids.iter().collect()
+	} else {
+		new_asteroids.iter().collect()
+	};
+
+	let width = 2. * ASTEROID_RADIUS;
+	for (entity, Asteroid(kind)) in asteroids {
+		spawn_glyph(
+			commands,
+			glyph_materials.badges[*kind].clone(),
+			entity,
+			Vec3::new(0., 0., 0.2),
+			BADGE_SIZE * width,
+		);
+		for (x, y) in pips(*kind) {
+			spawn_glyph(
+				commands,
+				glyph_materials.pips[*kind].clone(),
+				entity,
+				Vec3::new(x * PIP_SPACING * width, y * PIP_SPACING * width, 0.3),
+				PIP_SIZE * width,
+			);
+		}
+	}
+}
+
+/// Moves badges over their asteroid, and removes the ones whose asteroid is gone.
+pub fn place_glyphs(
+	commands: &mut Commands,
+	asteroids: Query<&GlobalTransform, (With<Asteroid>, Without<Glyph>)>,
+	mut glyphs: Query<(Entity, &Glyph, &mut GlobalTransform)>,
+) {
+	for (entity, glyph, mut transform) in glyphs.iter_mut() {
+		match asteroids.get(glyph.asteroid) {
+			Ok(asteroid) => {
+				transform.translation = asteroid.translation + glyph.offset * asteroid.scale;
+				transform.scale = asteroid.scale;
+			}
+			Err(_) => {
+				commands.despawn(entity);
+			}
+		}
+	}
+}
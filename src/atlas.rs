@@ -0,0 +1,447 @@
+use std::{collections::HashMap, error::Error, fmt, path::PathBuf, str};
+
+use bevy::{
+	asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+	prelude::*,
+	reflect::TypeUuid,
+	utils::BoxedFuture,
+};
+use bevy_egui::EguiContext;
+use serde::Deserialize;
+
+use crate::{
+	constants::{
+		ASTEROID_SPRITES, ATLAS_PATH, DNA_SPRITE, EGUI_TEXTURE_ATLAS_ID, EXPLOSION_ANIMATION,
+		PLANET_SPRITE,
+	},
+	level::Levels,
+	loading::{GameAssets, Loading},
+	recipes::Recipes,
+};
+
+/// A sprite as it is written in the atlas file.
+#[derive(Deserialize)]
+struct SpriteDescription {
+	name: String,
+	min: (f32, f32),
+	max: (f32, f32),
+}
+
+/// A frame as it is written in the atlas file, with the sprite given by its name.
+#[derive(Deserialize)]
+struct FrameDescription {
+	sprite: String,
+	duration: f32,
+}
+
+#[derive(Deserialize)]
+struct AtlasDescription {
+	texture: String,
+	size: (f32, f32),
+	sprites: Vec<SpriteDescription>,
+	#[serde(default)]
+	animations: HashMap<String, Vec<FrameDescription>>,
+}
+
+/// A frame of an animation: the index of a sprite, and how many seconds it is shown for.
+#[derive(Clone, Copy)]
+pub struct Frame {
+	pub sprite: u32,
+	pub duration: f32,
+}
+
+#[derive(Clone)]
+pub struct Animation {
+	pub frames: Vec<Frame>,
+}
+
+/// Where the sprites are in the texture, and the animations made of them, by name.
+///
+/// Loaded as an asset, whose last valid version is kept as a resource for the sprites to use.
+#[derive(Clone, Default, TypeUuid)]
+#[uuid = "40b65897-acd7-4a09-b50a-387027ab7d81"]
+pub struct SpriteAtlas {
+	/// Path of the texture in the `assets` folder.
+	pub texture: String,
+	pub size: Vec2,
+	/// Corners of each sprite, in the order of their indices in the texture atlas.
+	pub rects: Vec<(Vec2, Vec2)>,
+	indices: HashMap<String, u32>,
+	animations: HashMap<String, Animation>,
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+	Parse(ron::Error),
+	DuplicateSprite(String),
+	/// A sprite whose rectangle is empty or out of the texture.
+	InvalidRect(String),
+	EmptyAnimation(String),
+	/// An animation, with a frame that isn't shown for a positive duration.
+	NotPositive(String),
+	MissingSprite(String),
+	MissingAnimation(String),
+	/// Something referring to a sprite that doesn't exist, and the name of that sprite.
+	UnknownSprite(String, String),
+}
+
+impl fmt::Display for AtlasError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			AtlasError::Parse(err) => write!(f, "could not parse file: {}", err),
+			AtlasError::DuplicateSprite(name) => {
+				write!(f, "there are several sprites named `{}`", name)
+			}
+			AtlasError::InvalidRect(name) => write!(
+				f,
+				"sprite `{}` must have a `min` smaller than its `max`, within the texture",
+				name
+			),
+			AtlasError::EmptyAnimation(name) => {
+				write!(f, "animation `{}` must have at least one frame", name)
+			}
+			AtlasError::NotPositive(name) => write!(
+				f,
+				"the frames of animation `{}` must have a positive duration",
+				name
+			),
+			AtlasError::MissingSprite(name) => write!(f, "there must be a sprite `{}`", name),
+			AtlasError::MissingAnimation(name) => {
+				write!(f, "there must be an animation `{}`", name)
+			}
+			AtlasError::UnknownSprite(user, sprite) => {
+				write!(f, "{} uses unknown sprite `{}`", user, sprite)
+			}
+		}
+	}
+}
+
+impl Error for AtlasError {}
+
+impl SpriteAtlas {
+	pub fn from_ron(contents: &str) -> Result<Self, AtlasError> {
+		let description: AtlasDescription =
+			ron::de::from_str(contents).map_err(AtlasError::Parse)?;
+		let size = Vec2::new(description.size.0, description.size.1);
+
+		let mut rects = Vec::with_capacity(description.sprites.len());
+		let mut indices = HashMap::new();
+		for sprite in description.sprites {
+			let min = Vec2::new(sprite.min.0, sprite.min.1);
+			let max = Vec2::new(sprite.max.0, sprite.max.1);
+			if min.x < 0.
+				|| min.y < 0.
+				|| min.x >= max.x
+				|| min.y >= max.y
+				|| max.x > size.x
+				|| max.y > size.y
+			{
+				return Err(AtlasError::InvalidRect(sprite.name));
+			}
+			if indices.contains_key(&sprite.name) {
+				return Err(AtlasError::DuplicateSprite(sprite.name));
+			}
+			indices.insert(sprite.name, rects.len() as u32);
+			rects.push((min, max));
+		}
+
+		let mut animations = HashMap::new();
+		for (name, frames) in description.animations {
+			if frames.is_empty() {
+				return Err(AtlasError::EmptyAnimation(name));
+			}
+			if frames.iter().any(|frame| frame.duration <= 0.) {
+				return Err(AtlasError::NotPositive(name));
+			}
+			let frames = frames
+				.into_iter()
+				.map(|frame| match indices.get(&frame.sprite) {
+					Some(sprite) => Ok(Frame {
+						sprite: *sprite,
+						duration: frame.duration,
+					}),
+					None => Err(AtlasError::UnknownSprite(
+						format!("animation `{}`", name),
+						frame.sprite,
+					)),
+				})
+				.collect::<Result<_, _>>()?;
+			animations.insert(name, Animation { frames });
+		}
+
+		// Sprites and animations the code refers to
+		let sprites = [PLANET_SPRITE, DNA_SPRITE]
+			.iter()
+			.chain(ASTEROID_SPRITES.iter());
+		for sprite in sprites {
+			if !indices.contains_key(*sprite) {
+				return Err(AtlasError::MissingSprite(sprite.to_string()));
+			}
+		}
+		if !animations.contains_key(EXPLOSION_ANIMATION) {
+			return Err(AtlasError::MissingAnimation(
+				EXPLOSION_ANIMATION.to_string(),
+			));
+		}
+
+		Ok(Self {
+			texture: description.texture,
+			size,
+			rects,
+			indices,
+			animations,
+		})
+	}
+
+	/// Checks that the sprites the recipes and levels refer to exist.
+	pub fn check(&self, recipes: &Recipes, levels: &Levels) -> Result<(), AtlasError> {
+		for recipe in recipes.0.iter() {
+			let appearance = &recipe.appearance;
+			let sprites = appearance
+				.sprite
+				.iter()
+				.chain(appearance.overlays.iter().map(|overlay| &overlay.sprite));
+			for sprite in sprites {
+				if !self.indices.contains_key(sprite) {
+					return Err(AtlasError::UnknownSprite(
+						format!("recipe `{}`", recipe.name),
+						sprite.clone(),
+					));
+				}
+			}
+		}
+		for level in levels.iter() {
+			for planet in level.planets.iter() {
+				if !self.indices.contains_key(&planet.sprite) {
+					return Err(AtlasError::UnknownSprite(
+						format!("level `{}`", level.name),
+						planet.sprite.clone(),
+					));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Index of a sprite in the texture atlas.
+	///
+	/// Panics if there is no such sprite, which can't happen for the ones checked when loading.
+	pub fn sprite(&self, name: &str) -> u32 {
+		match self.indices.get(name) {
+			Some(index) => *index,
+			None => panic!("there is no sprite `{}`", name),
+		}
+	}
+
+	/// Panics if there is no such animation, which can't happen for the ones checked when
+	/// loading.
+	pub fn animation(&self, name: &str) -> &Animation {
+		match self.animations.get(name) {
+			Some(animation) => animation,
+			None => panic!("there is no animation `{}`", name),
+		}
+	}
+
+	pub fn texture_atlas(&self, texture: Handle<Texture>) -> TextureAtlas {
+		let mut texture_atlas = TextureAtlas::new_empty(texture, self.size);
+		for (min, max) in self.rects.iter() {
+			texture_atlas.add_texture(bevy::sprite::Rect {
+				min: *min,
+				max: *max,
+			});
+		}
+		texture_atlas
+	}
+}
+
+/// Reads atlas files, and loads their texture along with them.
+#[derive(Default)]
+pub struct SpriteAtlasLoader;
+
+impl AssetLoader for SpriteAtlasLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let atlas = SpriteAtlas::from_ron(str::from_utf8(bytes)?)?;
+			let texture = AssetPath::new(PathBuf::from(&atlas.texture), None);
+			load_context.set_default_asset(LoadedAsset::new(atlas).with_dependency(texture));
+			Ok(())
+		})
+	}
+
+	/// Only the atlas is loaded through the asset server, the other RON files are read directly.
+	fn extensions(&self) -> &[&str] {
+		&["ron"]
+	}
+}
+
+/// Reads the sprite atlas, and swaps it for the new one whenever its file changes.
+///
+/// Needs the recipes and levels, whose sprites are checked against the atlas.
+pub struct AtlasPlugin;
+
+impl Plugin for AtlasPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_asset::<SpriteAtlas>()
+			.init_asset_loader::<SpriteAtlasLoader>()
+			.init_resource::<SpriteAtlas>()
+			.add_system(use_loaded_atlas.system());
+	}
+}
+
+/// Uses the atlas once it is loaded and each time it is reloaded, as long as it has the sprites
+/// the recipes and levels refer to, and keeps the previous one otherwise.
+///
+/// Sprites already on screen keep their index, so they only look right after a reload that
+/// doesn't move existing sprites around.
+#[allow(clippy::too_many_arguments)]
+pub fn use_loaded_atlas(
+	mut atlas_reader: Local<EventReader<AssetEvent<SpriteAtlas>>>,
+	atlas_events: Res<Events<AssetEvent<SpriteAtlas>>>,
+	atlases: Res<Assets<SpriteAtlas>>,
+	asset_server: Res<AssetServer>,
+	recipes: Res<Recipes>,
+	levels: Res<Levels>,
+	mut atlas: ResMut<SpriteAtlas>,
+	game_assets: Res<GameAssets>,
+	mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+	mut egui_context: ResMut<EguiContext>,
+	mut loading: ResMut<Loading>,
+) {
+	for event in atlas_reader.iter(&atlas_events) {
+		let handle = match event {
+			AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+			AssetEvent::Removed { .. } => continue,
+		};
+		let loaded = match atlases.get(handle) {
+			Some(loaded) => loaded,
+			None => continue,
+		};
+		if let Err(err) = loaded.check(&recipes, &levels) {
+			warn!("could not use `{}`: {}", ATLAS_PATH, err);
+			loading.error = Some(format!("could not use `{}`: {}", ATLAS_PATH, err));
+			continue;
+		}
+		loading.atlas_ready = true;
+		loading.error = None;
+
+		*atlas = loaded.clone();
+		let texture: Handle<Texture> = asset_server.load(atlas.texture.as_str());
+		egui_context.set_egui_texture(EGUI_TEXTURE_ATLAS_ID, texture.clone());
+		texture_atlases.set(&game_assets.sprites, atlas.texture_atlas(texture));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use super::*;
+
+	/// An atlas with the sprites the code needs, other sprites, and animations, e.g.
+	/// `"explosion": [(sprite: "dna", duration: 0.4)]`.
+	fn atlas(sprites: &str, animations: &str) -> String {
+		let required = [PLANET_SPRITE, DNA_SPRITE]
+			.iter()
+			.chain(ASTEROID_SPRITES.iter())
+			.enumerate()
+			.map(|(i, name)| {
+				let x = 10. * i as f32;
+				format!(
+					"(name: \"{}\", min: ({}, 0.0), max: ({}, 10.0)),",
+					name,
+					x,
+					x + 10.
+				)
+			})
+			.collect::<String>();
+		format!(
+			"(texture: \"test.png\", size: (200.0, 100.0), sprites: [{}{}], animations: {{{}}})",
+			required, sprites, animations
+		)
+	}
+
+	const EXPLOSION: &str = r#""explosion": [(sprite: "dna", duration: 0.4)]"#;
+
+	fn error_of(contents: &str) -> AtlasError {
+		match SpriteAtlas::from_ron(contents) {
+			Ok(_) => panic!("expected the atlas to be rejected"),
+			Err(err) => err,
+		}
+	}
+
+	#[test]
+	fn loads_the_shipped_atlas() {
+		let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/images/textures.ron");
+		let contents = fs::read_to_string(path).unwrap();
+		let atlas = SpriteAtlas::from_ron(&contents)
+			.unwrap_or_else(|err| panic!("invalid shipped atlas: {}", err));
+		assert!(!atlas.animation(EXPLOSION_ANIMATION).frames.is_empty());
+	}
+
+	#[test]
+	fn refers_to_sprites_by_name() {
+		let atlas = SpriteAtlas::from_ron(&atlas("", EXPLOSION)).unwrap();
+		assert_eq!(atlas.sprite(PLANET_SPRITE), 0);
+		assert_eq!(atlas.sprite(DNA_SPRITE), 1);
+		assert_eq!(atlas.animation(EXPLOSION_ANIMATION).frames[0].sprite, 1);
+	}
+
+	#[test]
+	fn rejects_a_sprite_named_twice() {
+		let err = error_of(&atlas(
+			r#"(name: "dna", min: (0.0, 50.0), max: (10.0, 60.0)),"#,
+			EXPLOSION,
+		));
+		assert!(matches!(err, AtlasError::DuplicateSprite(name) if name == DNA_SPRITE));
+	}
+
+	#[test]
+	fn rejects_invalid_rects() {
+		let rects = [
+			"min: (20.0, 50.0), max: (10.0, 60.0)",
+			"min: (10.0, 50.0), max: (10.0, 60.0)",
+			"min: (-1.0, 50.0), max: (10.0, 60.0)",
+			"min: (190.0, 50.0), max: (210.0, 60.0)",
+			"min: (0.0, 90.0), max: (10.0, 110.0)",
+		];
+		for rect in rects.iter() {
+			let sprite = format!("(name: \"extra\", {}),", rect);
+			let err = error_of(&atlas(&sprite, EXPLOSION));
+			assert!(matches!(err, AtlasError::InvalidRect(name) if name == "extra"));
+		}
+	}
+
+	#[test]
+	fn rejects_an_animation_with_an_unknown_sprite() {
+		let err = error_of(&atlas(
+			"",
+			r#""explosion": [(sprite: "dna", duration: 0.4), (sprite: "smoke", duration: 0.4)]"#,
+		));
+		assert!(matches!(
+			err,
+			AtlasError::UnknownSprite(user, sprite)
+				if user == "animation `explosion`" && sprite == "smoke"
+		));
+	}
+
+	#[test]
+	fn rejects_empty_animations_and_frames_that_are_not_positive() {
+		let err = error_of(&atlas("", r#""explosion": []"#));
+		assert!(matches!(err, AtlasError::EmptyAnimation(name) if name == EXPLOSION_ANIMATION));
+		let err = error_of(&atlas(
+			"",
+			r#""explosion": [(sprite: "dna", duration: 0.0)]"#,
+		));
+		assert!(matches!(err, AtlasError::NotPositive(name) if name == EXPLOSION_ANIMATION));
+	}
+
+	#[test]
+	fn rejects_a_missing_animation() {
+		let err = error_of(&atlas("", r#""smoke": [(sprite: "dna", duration: 0.4)]"#));
+		assert!(matches!(err, AtlasError::MissingAnimation(name) if name == EXPLOSION_ANIMATION));
+	}
+}
@@ -0,0 +1,260 @@
+use bevy::prelude::*;
+use bevy_kira_audio::{Audio, AudioChannel, AudioPlugin as KiraAudioPlugin, AudioSource};
+
+use crate::{
+	bodies::{Impact, Planet},
+	config::GameConfig,
+	constants::{IMPACT_LOUD_SPEED, MUSIC_FADE_SPEED, MUSIC_LAYERS, N_ASTEROIDS, SOUNDS_PATH},
+	current_recipe,
+	loading::GameAssets,
+	recipes::{IngredientsComparison, Recipes},
+	slingshot::{slingshot_tension, Slingshot, SlingshotReleased},
+	state::AppState,
+	Biomass,
+};
+
+/// Volumes chosen by the player, from 0 to 1.
+///
+/// They only last for the session, like the colorblind mode, and are back to their defaults on
+/// the next launch.
+pub struct AudioSettings {
+	pub master: f32,
+	pub music: f32,
+	pub effects: f32,
+}
+
+impl Default for AudioSettings {
+	fn default() -> Self {
+		Self {
+			master: 0.8,
+			music: 0.6,
+			effects: 1.,
+		}
+	}
+}
+
+impl AudioSettings {
+	fn music(&self) -> f32 {
+		self.master * self.music
+	}
+
+	fn effects(&self) -> f32 {
+		self.master * self.effects
+	}
+}
+
+/// Sounds of the game, loaded along with the other assets.
+pub struct Sounds {
+	/// Looped while a slingshot is drawn.
+	pub stretch: Handle<AudioSource>,
+	pub snap: Handle<AudioSource>,
+	pub impact: Handle<AudioSource>,
+	pub evolution: Handle<AudioSource>,
+	pub extinction: Handle<AudioSource>,
+	/// Loops of the same length played together, more of them being heard as planets evolve.
+	pub music: Vec<Handle<AudioSource>>,
+}
+
+impl Sounds {
+	pub fn load(asset_server: &AssetServer) -> Self {
+		let load = |name: &str| asset_server.load(format!("{}/{}.wav", SOUNDS_PATH, name).as_str());
+		Self {
+			stretch: load("stretch"),
+			snap: load("snap"),
+			impact: load("impact"),
+			evolution: load("evolution"),
+			extinction: load("extinction"),
+			music: (0..MUSIC_LAYERS)
+				.map(|layer| load(&format!("music_{}", layer)))
+				.collect(),
+		}
+	}
+
+	pub fn handles(&self) -> impl Iterator<Item = &Handle<AudioSource>> {
+		vec![
+			&self.stretch,
+			&self.snap,
+			&self.impact,
+			&self.evolution,
+			&self.extinction,
+		]
+		.into_iter()
+		.chain(self.music.iter())
+	}
+}
+
+/// Channels sounds are played in, so that their volume and pitch can be set separately.
+pub struct AudioChannels {
+	slingshot: AudioChannel,
+	/// The snap of released slingshots, whose volume depends on how far they were drawn.
+	snap: AudioChannel,
+	/// Evolutions and extinctions, which must not be turned down by a quiet snap.
+	stingers: AudioChannel,
+	/// One per kind of asteroid, each with its own pitch.
+	impacts: Vec<AudioChannel>,
+	music: Vec<AudioChannel>,
+}
+
+impl Default for AudioChannels {
+	fn default() -> Self {
+		Self {
+			slingshot: AudioChannel::new("slingshot".to_string()),
+			snap: AudioChannel::new("snap".to_string()),
+			stingers: AudioChannel::new("stingers".to_string()),
+			impacts: (0..N_ASTEROIDS)
+				.map(|kind| AudioChannel::new(format!("impact_{}", kind)))
+				.collect(),
+			music: (0..MUSIC_LAYERS)
+				.map(|layer| AudioChannel::new(format!("music_{}", layer)))
+				.collect(),
+		}
+	}
+}
+
+/// Pitch of the impacts of a kind of asteroid, from low for the first kinds to high for the last.
+fn impact_pitch(kind: usize) -> f32 {
+	0.7 + 0.6 * kind as f32 / (N_ASTEROIDS - 1) as f32
+}
+
+/// Sounds of the slingshot, of impacts and of evolution, and music growing with life.
+pub struct AudioPlugin;
+
+impl Plugin for AudioPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_plugin(KiraAudioPlugin)
+			.add_resource(AudioSettings::default())
+			.add_resource(AudioChannels::default())
+			.add_system(slingshot_sounds.system())
+			.add_system(impact_sounds.system())
+			.add_system(layer_music.system());
+	}
+}
+
+/// Stretches while slingshots are drawn, higher the further they are, and snaps when they are
+/// released.
+#[allow(clippy::too_many_arguments)]
+pub fn slingshot_sounds(
+	audio: Res<Audio>,
+	settings: Res<AudioSettings>,
+	channels: Res<AudioChannels>,
+	game_assets: Res<GameAssets>,
+	config: Res<GameConfig>,
+	mut stretching: Local<bool>,
+	mut release_reader: Local<EventReader<SlingshotReleased>>,
+	releases: Res<Events<SlingshotReleased>>,
+	slingshots: Query<&Slingshot>,
+	asteroids: Query<&Transform>,
+) {
+	let tension = slingshots
+		.iter()
+		.filter_map(|slingshot| {
+			let asteroid = asteroids.get(slingshot.target).ok()?;
+			Some(slingshot_tension(
+				&config,
+				asteroid.translation.truncate(),
+				slingshot.aim,
+			))
+		})
+		.fold(None, |max: Option<f32>, tension| {
+			Some(max.map_or(tension, |max| max.max(tension)))
+		});
+
+	match tension {
+		Some(tension) => {
+			if !*stretching {
+				audio.play_looped_in_channel(
+					game_assets.sounds.stretch.clone(),
+					&channels.slingshot,
+				);
+				*stretching = true;
+			}
+			audio.set_pitch_in_channel(0.8 + 0.8 * tension, &channels.slingshot);
+			audio.set_volume_in_channel(0.4 * settings.effects(), &channels.slingshot);
+		}
+		None if *stretching => {
+			audio.stop_channel(&channels.slingshot);
+			*stretching = false;
+		}
+		None => {}
+	}
+
+	for release in release_reader.iter(&releases) {
+		audio.set_volume_in_channel(
+			(0.4 + 0.6 * release.tension) * settings.effects(),
+			&channels.snap,
+		);
+		audio.play_in_channel(game_assets.sounds.snap.clone(), &channels.snap);
+	}
+}
+
+/// Thuds as asteroids crash, pitched by their kind and louder the faster they go, along with a
+/// stinger when a planet completes its recipe or suffers an extinction.
+pub fn impact_sounds(
+	audio: Res<Audio>,
+	settings: Res<AudioSettings>,
+	channels: Res<AudioChannels>,
+	game_assets: Res<GameAssets>,
+	mut impact_reader: Local<EventReader<Impact>>,
+	impacts: Res<Events<Impact>>,
+) {
+	for impact in impact_reader.iter(&impacts) {
+		let channel = &channels.impacts[impact.asteroid];
+		let loudness = (impact.velocity.length() / IMPACT_LOUD_SPEED).clamp(0.2, 1.);
+		audio.set_pitch_in_channel(impact_pitch(impact.asteroid), channel);
+		audio.set_volume_in_channel(loudness * settings.effects(), channel);
+		audio.play_in_channel(game_assets.sounds.impact.clone(), channel);
+
+		let stinger = match impact.outcome {
+			Some(IngredientsComparison::JustRight) => Some(&game_assets.sounds.evolution),
+			Some(IngredientsComparison::TooMany) => Some(&game_assets.sounds.extinction),
+			_ => None,
+		};
+		if let Some(stinger) = stinger {
+			audio.set_volume_in_channel(settings.effects(), &channels.stingers);
+			audio.play_in_channel(stinger.clone(), &channels.stingers);
+		}
+	}
+}
+
+/// Plays every layer of the music from the end of the loading, and fades layers in as the most
+/// evolved planet moves up the recipes, and out as it goes back down.
+#[allow(clippy::too_many_arguments)]
+pub fn layer_music(
+	audio: Res<Audio>,
+	time: Res<Time>,
+	settings: Res<AudioSettings>,
+	channels: Res<AudioChannels>,
+	game_assets: Res<GameAssets>,
+	state: Res<State<AppState>>,
+	recipes: Res<Recipes>,
+	mut volumes: Local<Vec<f32>>,
+	planets: Query<&Biomass, With<Planet>>,
+) {
+	if *state.current() == AppState::Loading {
+		return;
+	}
+
+	// Layers are all started at once so that they stay in time
+	if volumes.is_empty() {
+		for (sound, channel) in game_assets.sounds.music.iter().zip(channels.music.iter()) {
+			audio.set_volume_in_channel(0., channel);
+			audio.play_looped_in_channel(sound.clone(), channel);
+		}
+		*volumes = vec![0.; MUSIC_LAYERS];
+	}
+
+	let stage = planets
+		.iter()
+		.map(|biomass| current_recipe(&recipes, biomass.0))
+		.max()
+		.unwrap_or(0);
+	let last_stage = (recipes.len() - 1).max(1);
+	let layers = 1 + stage * (MUSIC_LAYERS - 1) / last_stage;
+
+	let step = MUSIC_FADE_SPEED * time.delta_seconds();
+	for (layer, (volume, channel)) in volumes.iter_mut().zip(channels.music.iter()).enumerate() {
+		let target = if layer < layers { 1. } else { 0. };
+		*volume += (target - *volume).max(-step).min(step);
+		audio.set_volume_in_channel(*volume * settings.music(), channel);
+	}
+}
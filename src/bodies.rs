@@ -1,125 +1,169 @@
+use std::f32::consts::TAU;
+
 use bevy::prelude::*;
-// use bevy_rng::*;
 use rand::prelude::*;
 
 use crate::{
-	add_ingredient,
+	config::GameConfig,
 	constants::{
-		ASTEROID_ANGULAR_VELOCITY, ASTEROID_DESPAWN_DISTANCE, ASTEROID_INIT_VELOCITY_NORMAL,
-		ASTEROID_INIT_VELOCITY_RADIAL, ASTEROID_SPAWN_DISTANCE, ASTEROID_SPRITES, EXPLOSION_FRAMES,
-		EXPLOSION_PERIOD, EXTINCTION_RATE, N_ASTEROIDS, RECIPES,
+		ASTEROID_DESPAWN_DISTANCE, ASTEROID_RADIUS, ASTEROID_SPAWN_DISTANCE, INITIAL_BIOMASS,
+		PLANET_RADIUS,
 	},
 	current_recipe,
-	physics::{AngularVelocity, Velocity},
-	screen_shaker::{ScreenShakeTimer, ScreenShaker},
-	Biomass, CurrentIngredients, IngredientsComparison,
+	level::{Level, Levels},
+	physics::{AngularVelocity, Mass, Orbit, SimulationClock, Velocity},
+	recipes::{add_ingredient, IngredientsComparison, Recipes},
+	rng::GameRng,
+	spatial::SpatialGrid,
+	spawning::{speed_factor, SpawnDirector, SpawnScheduler},
+	Biomass, CurrentIngredients,
 };
 
 pub struct Planet;
 
-pub struct Asteroid(usize);
+/// Name of the sprite of a planet in the atlas.
+pub struct PlanetSprite(pub String);
 
-pub struct Radius(pub f32);
+/// A body orbiting a planet, which asteroids crash into without feeding anything.
+pub struct Moon;
 
-impl Radius {
-	pub fn contains(&self, point: Vec2) -> bool {
-		point.length() <= self.0
-	}
-}
+/// Planets and moons, which stay on their course whatever crashes into them.
+///
+/// Bevy can't tell from `Or` alone that they are never asteroids, and would refuse to run systems
+/// that also change asteroids.
+pub type Celestial = (Or<(With<Planet>, With<Moon>)>, Without<Asteroid>);
 
-pub struct Explosion {
-	pub timer: Timer,
-	pub frame: usize,
-}
+/// An obstacle pulling asteroids in, or pushing them away, and destroying the ones that reach it.
+pub struct GravityWell;
 
-impl Explosion {
-	pub fn new() -> Self {
-		Explosion {
-			timer: Timer::from_seconds(EXPLOSION_PERIOD, true),
-			frame: 0,
-		}
-	}
-}
+/// Moons and gravity wells, which destroy the asteroids reaching them and nothing more.
+type Obstacle = (With<Mass>, Without<Planet>);
 
-pub struct AsteroidSpawerTimer(pub Timer);
+pub struct Asteroid(pub usize);
 
-pub fn animate_explosion(
-	time: Res<Time>,
-	commands: &mut Commands,
-	mut query: Query<(Entity, &mut Explosion, &mut TextureAtlasSprite)>,
-) {
-	for (entity, mut explosion, mut sprite) in query.iter_mut() {
-		if explosion.timer.tick(time.delta_seconds()).just_finished() {
-			explosion.frame += 1;
-			if explosion.frame >= EXPLOSION_FRAMES.len() {
-				commands.despawn(entity);
-			} else {
-				sprite.index = EXPLOSION_FRAMES[explosion.frame];
-			}
-		}
-	}
+pub struct Radius(pub f32);
+
+/// Sent whenever an asteroid crashes into a planet, a moon or a gravity well.
+pub struct Impact {
+	/// The planet, moon or gravity well.
+	pub body: Entity,
+	pub position: Vec3,
+	pub velocity: Vec2,
+	pub asteroid: usize,
+	/// How the ingredients of the planet compare to its recipe after the impact, or `None` if
+	/// the body isn't a planet.
+	pub outcome: Option<IngredientsComparison>,
 }
 
+/// Sent whenever a planet evolves to another recipe, or goes back to a previous one.
+pub struct StageChanged {
+	pub planet: Entity,
+	pub previous: usize,
+	pub current: usize,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn collision(
 	commands: &mut Commands,
-	texture_atlases: Res<Assets<TextureAtlas>>,
-	asteroids: Query<(Entity, &Transform, &Radius, &Velocity, &Asteroid)>,
-	planets: Query<(&Transform, &Radius), With<Planet>>,
-	mut screen_shaker: Query<&mut ScreenShaker>,
-	mut screen_shaker_timer: ResMut<ScreenShakeTimer>,
-	mut biomass: ResMut<Biomass>,
-	mut current_ingredients: ResMut<CurrentIngredients>,
+	grid: Res<SpatialGrid>,
+	asteroids: Query<(&Transform, &Velocity, &Asteroid)>,
+	mut planets: Query<
+		(
+			Entity,
+			&Transform,
+			&Radius,
+			&mut Biomass,
+			&mut CurrentIngredients,
+		),
+		With<Planet>,
+	>,
+	obstacles: Query<(Entity, &Transform, &Radius), Obstacle>,
+	mut impacts: ResMut<Events<Impact>>,
+	mut stage_changes: ResMut<Events<StageChanged>>,
+	config: Res<GameConfig>,
+	recipes: Res<Recipes>,
 ) {
-	let mut explosions = vec![];
-	for (entity, asteroid_t, Radius(asteroid_radius), Velocity(velocity), Asteroid(asteroid)) in
-		asteroids.iter()
+	// An asteroid touching two bodies at once only crashes into the first one
+	let mut crashed = vec![];
+
+	for (planet, planet_t, Radius(planet_radius), mut biomass, mut current_ingredients) in
+		planets.iter_mut()
 	{
-		for (planet_t, Radius(planet_radius)) in planets.iter() {
-			let r = (planet_t.translation - asteroid_t.translation).length();
-			// If the asteroid collides with the planet
-			if r <= asteroid_radius + planet_radius {
+		// Asteroids colliding with the planet
+		for entry in grid.query_circle(planet_t.translation.truncate(), *planet_radius) {
+			if crashed.contains(&entry.entity) {
+				continue;
+			}
+			if let Ok((asteroid_t, Velocity(velocity), Asteroid(asteroid))) =
+				asteroids.get(entry.entity)
+			{
 				// Despawn the asteroid
-				commands.despawn(entity);
-
-				// Store the asteroid's location to later spawn an explosion.  We have to do
-				// this because, for some reason, if we do it in the loop, it doesn't work .__.
-				explosions.push(asteroid_t.clone());
-
-				// Reset screen shaker timer & set direction to velocity of the asteroid
-				for mut screen_shaker in screen_shaker.iter_mut() {
-					screen_shaker.direction = velocity.normalize();
-					screen_shaker_timer.0.reset();
+				commands.despawn(entry.entity);
+				crashed.push(entry.entity);
+				if entry.radius < config.asteroid_min_ingredient_radius {
+					// Too small to count, it burns up in the atmosphere
+					continue;
 				}
 
 				let ingredients = add_ingredient(&current_ingredients.0, *asteroid);
-				let recipe = &RECIPES[current_recipe(biomass.0)];
-				match recipe.check_ingredients(&ingredients) {
+				let previous_stage = current_recipe(&recipes, biomass.0);
+				let recipe = &recipes[previous_stage];
+				let outcome = recipe.check_ingredients(&ingredients);
+				match outcome {
 					IngredientsComparison::TooFew => {
 						*current_ingredients = CurrentIngredients(ingredients);
 					}
 					IngredientsComparison::TooMany => {
 						*current_ingredients = CurrentIngredients::new();
-						biomass.0 = (biomass.0 as f32 / EXTINCTION_RATE).max(1.) as u32;
+						biomass.0 = (biomass.0 as f32 / config.extinction_rate).max(1.) as u32;
 					}
 					IngredientsComparison::JustRight => {
 						*current_ingredients = CurrentIngredients::new();
 						biomass.0 += recipe.reward;
 					}
 				}
+
+				impacts.send(Impact {
+					body: planet,
+					position: asteroid_t.translation,
+					velocity: *velocity,
+					asteroid: *asteroid,
+					outcome: Some(outcome),
+				});
+
+				let stage = current_recipe(&recipes, biomass.0);
+				if stage != previous_stage {
+					stage_changes.send(StageChanged {
+						planet,
+						previous: previous_stage,
+						current: stage,
+					});
+				}
 			}
 		}
 	}
 
-	// Spawn an explosion for each impact
-	for transform in explosions {
-		commands
-			.spawn(SpriteSheetBundle {
-				texture_atlas: texture_atlases.get_handle(texture_atlases.ids().next().unwrap()),
-				sprite: TextureAtlasSprite::new(EXPLOSION_FRAMES[0]),
-				transform,
-				..Default::default()
-			})
-			.with(Explosion::new());
+	// Moons and gravity wells only destroy asteroids
+	for (obstacle, obstacle_t, Radius(obstacle_radius)) in obstacles.iter() {
+		for entry in grid.query_circle(obstacle_t.translation.truncate(), *obstacle_radius) {
+			if crashed.contains(&entry.entity) {
+				continue;
+			}
+			if let Ok((asteroid_t, Velocity(velocity), Asteroid(asteroid))) =
+				asteroids.get(entry.entity)
+			{
+				commands.despawn(entry.entity);
+				crashed.push(entry.entity);
+
+				impacts.send(Impact {
+					body: obstacle,
+					position: asteroid_t.translation,
+					velocity: *velocity,
+					asteroid: *asteroid,
+					outcome: None,
+				});
+			}
+		}
 	}
 }
 
@@ -127,42 +171,166 @@ fn vec2_from_radial(radius: f32, angle: f32) -> Vec2 {
 	radius * Vec2::new(angle.cos(), angle.sin())
 }
 
+/// Spawns asteroids headed for a random planet, at the pace of the most evolved one.
+#[allow(clippy::too_many_arguments)]
 pub fn asteroid_spawner(
-	// mut rng: Local<Rng>,
-	time: Res<Time>,
-	mut timer: ResMut<AsteroidSpawerTimer>,
+	clock: Res<SimulationClock>,
+	config: Res<GameConfig>,
+	recipes: Res<Recipes>,
+	levels: Res<Levels>,
+	planets: Query<(&Transform, &Biomass, &CurrentIngredients), With<Planet>>,
+	mut rng: ResMut<GameRng>,
+	mut director: ResMut<SpawnDirector>,
+	mut scheduler: ResMut<SpawnScheduler>,
 	commands: &mut Commands,
-	texture_atlases: Res<Assets<TextureAtlas>>,
 ) {
-	if timer.0.tick(time.delta_seconds()).just_finished() {
-		let mut rng = rand::thread_rng();
-		let angular_velocity =
-			[-1.0, 1.0].choose(&mut rng).unwrap() * rng.gen_range(ASTEROID_ANGULAR_VELOCITY);
-		let position = vec2_from_radial(
-			ASTEROID_SPAWN_DISTANCE,
-			rng.gen_range(0.0..std::f32::consts::TAU),
+	let planets: Vec<(Vec2, usize, &CurrentIngredients)> = planets
+		.iter()
+		.map(|(transform, biomass, current_ingredients)| {
+			(
+				transform.translation.truncate(),
+				current_recipe(&recipes, biomass.0),
+				current_ingredients,
+			)
+		})
+		.collect();
+	if planets.is_empty() {
+		return;
+	}
+
+	director.advance(clock.delta());
+	let stage = planets.iter().map(|(_, stage, _)| *stage).max().unwrap();
+	let speed = speed_factor(&config, stage);
+
+	let zones = &levels.current().spawn_zones;
+	for angle in scheduler.advance(&config, &mut rng, stage, zones, clock.delta()) {
+		let (target, target_stage, current_ingredients) = *planets.choose(&mut *rng).unwrap();
+		let recipe = &recipes[target_stage];
+		let asteroids = director.choose(
+			&config,
+			&mut rng,
+			&recipe.ingredients,
+			&current_ingredients.0,
 		);
-		let r = (Vec2::zero() - position).normalize();
-		let n = r.perp();
-		let velocity = rng.gen_range(ASTEROID_INIT_VELOCITY_RADIAL) * r
-			+ rng.gen_range(ASTEROID_INIT_VELOCITY_NORMAL) * n;
-		let asteroid = rng.gen_range(0..N_ASTEROIDS);
-		let sprite = TextureAtlasSprite::new(ASTEROID_SPRITES[asteroid]);
+		for (i, asteroid) in asteroids.into_iter().enumerate() {
+			// Starved kinds spawning along come from anywhere, rather than on top of each other
+			let angle = match i {
+				0 => angle,
+				_ => rng.gen_range(0.0..std::f32::consts::TAU),
+			};
+			let angular_velocity = [-1.0, 1.0].choose(&mut *rng).unwrap()
+				* rng.gen_range(config.asteroid_angular_velocity.clone());
+			let position = vec2_from_radial(ASTEROID_SPAWN_DISTANCE, angle);
+			let r = (target - position).normalize();
+			let n = r.perp();
+			let velocity = speed
+				* (rng.gen_range(config.asteroid_init_velocity_radial.clone()) * r
+					+ rng.gen_range(config.asteroid_init_velocity_normal.clone()) * n);
+
+			spawn_asteroid(
+				commands,
+				Transform::from_translation(position.extend(0.)),
+				velocity,
+				angular_velocity,
+				ASTEROID_RADIUS,
+				asteroid,
+			);
+		}
+	}
+}
 
+/// Spawns the planets of a level, their moons, and the gravity wells.
+pub fn spawn_bodies(commands: &mut Commands, level: &Level) {
+	for description in level.planets.iter() {
+		let position = Vec2::new(description.position.0, description.position.1);
+		let planet = spawn_planet(
+			commands,
+			Transform::from_translation(position.extend(0.)),
+			description.angular_velocity,
+			description.radius,
+			description.mass(),
+			description.sprite.clone(),
+		);
+		for moon in description.moons.iter() {
+			let orbit = Orbit {
+				center: planet,
+				distance: moon.distance,
+				angle: 0.,
+				angular_speed: TAU / moon.period,
+			};
+			spawn_moon(commands, position, orbit, moon.radius, moon.mass());
+		}
+	}
+
+	spawn_gravity_wells(commands, level);
+}
+
+pub fn spawn_gravity_wells(commands: &mut Commands, level: &Level) {
+	for well in level.gravity_wells.iter() {
+		let position = Vec3::new(well.position.0, well.position.1, 0.);
+		let mut transform = Transform::from_translation(position);
+		transform.scale = Vec3::splat(well.radius / PLANET_RADIUS);
 		commands
-			.spawn(SpriteSheetBundle {
-				texture_atlas: texture_atlases.get_handle(texture_atlases.ids().next().unwrap()),
-				sprite,
-				transform: Transform::from_translation(position.extend(0.)),
-				..Default::default()
-			})
-			.with(Velocity(velocity))
-			.with(AngularVelocity(angular_velocity))
-			.with(Radius(30.))
-			.with(Asteroid(asteroid));
+			.spawn((transform, GlobalTransform::default()))
+			.with(Radius(well.radius))
+			.with(Mass(well.mass))
+			.with(GravityWell);
 	}
 }
 
+/// Spawns a planet without any life yet, scaled according to its radius, and returns it so that
+/// moons can orbit it.
+pub fn spawn_planet(
+	commands: &mut Commands,
+	mut transform: Transform,
+	angular_velocity: f32,
+	radius: f32,
+	mass: f32,
+	sprite: String,
+) -> Entity {
+	transform.scale = Vec3::splat(radius / PLANET_RADIUS);
+	commands
+		.spawn((transform, GlobalTransform::default()))
+		.with(AngularVelocity(angular_velocity))
+		.with(Radius(radius))
+		.with(Mass(mass))
+		.with(Biomass(INITIAL_BIOMASS))
+		.with(CurrentIngredients::new())
+		.with(PlanetSprite(sprite))
+		.with(Planet);
+	commands.current_entity().unwrap()
+}
+
+/// Spawns a moon along its orbit around a planet at `center`.
+pub fn spawn_moon(commands: &mut Commands, center: Vec2, orbit: Orbit, radius: f32, mass: f32) {
+	let mut transform = Transform::from_translation((center + orbit.offset()).extend(0.));
+	transform.scale = Vec3::splat(radius / PLANET_RADIUS);
+	commands
+		.spawn((transform, GlobalTransform::default()))
+		.with(orbit)
+		.with(Radius(radius))
+		.with(Mass(mass))
+		.with(Moon);
+}
+
+/// Spawns an asteroid, scaled according to its radius.
+pub fn spawn_asteroid(
+	commands: &mut Commands,
+	mut transform: Transform,
+	velocity: Vec2,
+	angular_velocity: f32,
+	radius: f32,
+	kind: usize,
+) {
+	transform.scale = Vec3::splat(radius / ASTEROID_RADIUS);
+	commands
+		.spawn((transform, GlobalTransform::default()))
+		.with(Velocity(velocity))
+		.with(AngularVelocity(angular_velocity))
+		.with(Radius(radius))
+		.with(Asteroid(kind));
+}
+
 pub fn asteroid_despawner(
 	commands: &mut Commands,
 	query: Query<(Entity, &Transform), With<Asteroid>>,
@@ -173,3 +341,53 @@ pub fn asteroid_despawner(
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{constants::INITIAL_BIOMASS, spatial::rebuild_spatial_grid};
+
+	/// Crashes a teal asteroid of the given radius into a planet, and returns the resulting
+	/// biomass.
+	fn crash(radius: f32) -> u32 {
+		let config = GameConfig::from_ron(include_str!("../assets/config.ron")).unwrap();
+		let recipes = Recipes::from_ron(include_str!("../assets/recipes.ron")).unwrap();
+		let mut app = App::build();
+		app.add_resource(config)
+			.add_resource(recipes)
+			.add_event::<Impact>()
+			.add_event::<StageChanged>()
+			.add_resource(SpatialGrid::default())
+			.add_system(rebuild_spatial_grid.system())
+			.add_system(collision.system());
+		let planet = app.app.world.spawn((
+			Transform::default(),
+			Radius(100.),
+			Biomass(INITIAL_BIOMASS),
+			CurrentIngredients::new(),
+			Planet,
+		));
+		app.app.world.spawn((
+			Transform::from_translation(Vec3::new(100., 0., 0.)),
+			Velocity(Vec2::zero()),
+			Radius(radius),
+			Asteroid(0),
+		));
+
+		app.app.update();
+		assert_eq!(app.app.world.query::<&Asteroid>().count(), 0);
+		let biomass = app.app.world.get::<Biomass>(planet).unwrap().0;
+		biomass
+	}
+
+	#[test]
+	fn small_fragments_burn_up_without_counting() {
+		let config = GameConfig::from_ron(include_str!("../assets/config.ron")).unwrap();
+		assert_eq!(
+			crash(config.asteroid_min_ingredient_radius / 2.),
+			INITIAL_BIOMASS
+		);
+		// Teal is all the first recipe needs
+		assert!(crash(ASTEROID_RADIUS) > INITIAL_BIOMASS);
+	}
+}
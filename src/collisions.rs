@@ -0,0 +1,282 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	bodies::{spawn_asteroid, Asteroid, Radius},
+	config::GameConfig,
+	constants::ASTEROID_RADIUS,
+	physics::{AngularVelocity, Mass, Velocity},
+	spatial::SpatialGrid,
+};
+
+/// What happens when two asteroids run into each other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum AsteroidCollisions {
+	/// They bounce off each other.
+	Bounce,
+	/// The smaller one is absorbed by the bigger one, which grows.
+	Merge,
+	/// They break into smaller fragments of the same kind.
+	Shatter,
+}
+
+/// Copy of an asteroid while collisions are being resolved.
+#[derive(Clone, Copy)]
+struct Rock {
+	entity: Entity,
+	position: Vec2,
+	velocity: Vec2,
+	angular_velocity: f32,
+	radius: f32,
+	kind: usize,
+	/// Merged into another rock, or shattered.
+	gone: bool,
+}
+
+/// Asteroids all have the same density.
+fn mass(radius: f32) -> f32 {
+	radius * radius
+}
+
+/// Pushes two overlapping rocks apart, and makes them bounce off each other if they are getting
+/// closer.
+fn bounce(a: &mut Rock, b: &mut Rock, restitution: f32) {
+	let offset = b.position - a.position;
+	let distance = offset.length();
+	let normal = if distance > 0. {
+		offset / distance
+	} else {
+		Vec2::unit_x()
+	};
+	let (mass_a, mass_b) = (mass(a.radius), mass(b.radius));
+	let total = mass_a + mass_b;
+
+	let overlap = a.radius + b.radius - distance;
+	a.position -= overlap * mass_b / total * normal;
+	b.position += overlap * mass_a / total * normal;
+
+	let approach = (a.velocity - b.velocity).dot(normal);
+	if approach > 0. {
+		let impulse = (1. + restitution) * approach / total;
+		a.velocity -= impulse * mass_b * normal;
+		b.velocity += impulse * mass_a * normal;
+	}
+}
+
+/// Absorbs `other` into `into`, keeping the momentum and the total mass.
+fn merge(into: &mut Rock, other: &mut Rock) {
+	let (mass_into, mass_other) = (mass(into.radius), mass(other.radius));
+	let total = mass_into + mass_other;
+	into.position = (mass_into * into.position + mass_other * other.position) / total;
+	into.velocity = (mass_into * into.velocity + mass_other * other.velocity) / total;
+	into.angular_velocity =
+		(mass_into * into.angular_velocity + mass_other * other.angular_velocity) / total;
+	into.radius = total.sqrt();
+	other.gone = true;
+}
+
+/// Breaks a rock into fragments flying away from its center, placed so that they don't overlap.
+fn shatter(rock: &mut Rock, config: &GameConfig, commands: &mut Commands) {
+	let count = config.asteroid_fragments;
+	let radius = rock.radius / (count as f32).sqrt();
+	let distance = radius / (PI / count as f32).sin();
+	let start = rock.velocity.y.atan2(rock.velocity.x);
+	for i in 0..count {
+		let angle = start + i as f32 * 2. * PI / count as f32;
+		let direction = Vec2::new(angle.cos(), angle.sin());
+		spawn_asteroid(
+			commands,
+			Transform::from_translation((rock.position + distance * direction).extend(0.)),
+			rock.velocity + config.asteroid_fragment_speed * direction,
+			rock.angular_velocity,
+			radius,
+			rock.kind,
+		);
+	}
+	rock.gone = true;
+}
+
+pub fn asteroid_collisions(
+	commands: &mut Commands,
+	config: Res<GameConfig>,
+	grid: Res<SpatialGrid>,
+	mut asteroids: Query<(
+		Entity,
+		&mut Transform,
+		&mut Velocity,
+		&mut AngularVelocity,
+		&mut Radius,
+		&Asteroid,
+	)>,
+	bodies: Query<(&Transform, &Radius), With<Mass>>,
+) {
+	let pairs = grid.overlapping_pairs();
+	if pairs.is_empty() {
+		return;
+	}
+
+	let bodies: Vec<(Vec2, f32)> = bodies
+		.iter()
+		.map(|(transform, Radius(radius))| (transform.translation.truncate(), *radius))
+		.collect();
+	let crashing = |position: Vec2, radius: f32| {
+		bodies
+			.iter()
+			.any(|(body, body_radius)| (*body - position).length() <= radius + body_radius)
+	};
+
+	// Asteroids crashing into a planet, a moon or a gravity well are already taken care of
+	let mut rocks: Vec<Rock> = asteroids
+		.iter_mut()
+		.filter(|(_, transform, _, _, radius, _)| {
+			!crashing(transform.translation.truncate(), radius.0)
+		})
+		.map(
+			|(entity, transform, velocity, angular_velocity, radius, Asteroid(kind))| Rock {
+				entity,
+				position: transform.translation.truncate(),
+				velocity: velocity.0,
+				angular_velocity: angular_velocity.0,
+				radius: radius.0,
+				kind: *kind,
+				gone: false,
+			},
+		)
+		.collect();
+
+	let indices: HashMap<Entity, usize> = rocks
+		.iter()
+		.enumerate()
+		.map(|(index, rock)| (rock.entity, index))
+		.collect();
+
+	for (a, b) in pairs {
+		let (a, b) = match (indices.get(&a), indices.get(&b)) {
+			(Some(a), Some(b)) => (*a, *b),
+			_ => continue,
+		};
+		let (mut rock_a, mut rock_b) = (rocks[a], rocks[b]);
+		if rock_a.gone || rock_b.gone {
+			continue;
+		}
+
+		match config.asteroid_collisions {
+			AsteroidCollisions::Bounce => {
+				bounce(&mut rock_a, &mut rock_b, config.asteroid_restitution)
+			}
+			AsteroidCollisions::Merge => {
+				if rock_a.radius >= rock_b.radius {
+					merge(&mut rock_a, &mut rock_b);
+				} else {
+					merge(&mut rock_b, &mut rock_a);
+				}
+			}
+			AsteroidCollisions::Shatter => {
+				// Fragments fly away in the directions the rocks would have bounced to
+				bounce(&mut rock_a, &mut rock_b, config.asteroid_restitution);
+				for rock in [&mut rock_a, &mut rock_b].iter_mut() {
+					if rock.radius >= config.asteroid_min_shatter_radius {
+						shatter(rock, &config, commands);
+					}
+				}
+			}
+		}
+
+		rocks[a] = rock_a;
+		rocks[b] = rock_b;
+	}
+
+	for rock in rocks {
+		if rock.gone {
+			commands.despawn(rock.entity);
+		} else if let Ok((_, mut transform, mut velocity, mut angular_velocity, mut radius, _)) =
+			asteroids.get_mut(rock.entity)
+		{
+			transform.translation = rock.position.extend(transform.translation.z);
+			transform.scale = Vec3::splat(rock.radius / ASTEROID_RADIUS);
+			velocity.0 = rock.velocity;
+			angular_velocity.0 = rock.angular_velocity;
+			radius.0 = rock.radius;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::spatial::rebuild_spatial_grid;
+
+	/// Runs two asteroids of kinds 0 and 1 into each other, and returns the resulting positions,
+	/// velocities, radii and kinds.
+	fn collide(collisions: AsteroidCollisions) -> Vec<(Vec2, Vec2, f32, usize)> {
+		let mut config = GameConfig::from_ron(include_str!("../assets/config.ron")).unwrap();
+		config.asteroid_collisions = collisions;
+		let mut app = App::build();
+		app.add_resource(config)
+			.add_resource(SpatialGrid::default())
+			.add_system(rebuild_spatial_grid.system())
+			.add_system(asteroid_collisions.system());
+		for &(x, kind) in [(-25., 0), (25., 1)].iter() {
+			app.app.world.spawn((
+				Transform::from_translation(Vec3::new(x, 0., 0.)),
+				Velocity(Vec2::new(-x, 0.)),
+				AngularVelocity(0.),
+				Radius(ASTEROID_RADIUS),
+				Asteroid(kind),
+			));
+		}
+
+		app.app.update();
+		app.app
+			.world
+			.query::<(&Transform, &Velocity, &Radius, &Asteroid)>()
+			.map(|(transform, velocity, radius, asteroid)| {
+				(
+					transform.translation.truncate(),
+					velocity.0,
+					radius.0,
+					asteroid.0,
+				)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn bouncing_asteroids_fly_apart() {
+		let rocks = collide(AsteroidCollisions::Bounce);
+		assert_eq!(rocks.len(), 2);
+		let (a, b) = if rocks[0].3 == 0 {
+			(rocks[0], rocks[1])
+		} else {
+			(rocks[1], rocks[0])
+		};
+		assert!((b.0 - a.0).length() >= 2. * ASTEROID_RADIUS - 1e-3);
+		assert!(a.1.x < 0. && b.1.x > 0.);
+		assert!((a.1 + b.1).length() < 1e-3);
+	}
+
+	#[test]
+	fn merging_asteroids_become_one_bigger_asteroid() {
+		let rocks = collide(AsteroidCollisions::Merge);
+		assert_eq!(rocks.len(), 1);
+		let (position, velocity, radius, _) = rocks[0];
+		assert!(position.length() < 1e-3);
+		assert!(velocity.length() < 1e-3);
+		assert!((radius - 2f32.sqrt() * ASTEROID_RADIUS).abs() < 1e-3);
+	}
+
+	#[test]
+	fn shattering_asteroids_break_into_fragments_of_their_kind() {
+		let config = GameConfig::from_ron(include_str!("../assets/config.ron")).unwrap();
+		let rocks = collide(AsteroidCollisions::Shatter);
+		assert_eq!(rocks.len(), 2 * config.asteroid_fragments);
+		for kind in 0..2 {
+			let fragments: Vec<_> = rocks.iter().filter(|rock| rock.3 == kind).collect();
+			assert_eq!(fragments.len(), config.asteroid_fragments);
+			let total: f32 = fragments.iter().map(|rock| mass(rock.2)).sum();
+			assert!((total - mass(ASTEROID_RADIUS)).abs() < 1e-2);
+		}
+	}
+}
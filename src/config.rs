@@ -0,0 +1,401 @@
+use std::{
+	fmt, fs, io,
+	ops::Range,
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	asset_path,
+	collisions::AsteroidCollisions,
+	constants::{CONFIG_PATH, CONFIG_POLL_PERIOD},
+	physics::Integrator,
+};
+
+/// Gameplay tuning values, loaded from `assets/config.ron` and reloaded whenever that file
+/// changes.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameConfig {
+	#[serde(default)]
+	pub seed: Option<u64>,
+	pub gravity: f32,
+	/// Mass of an asteroid of `ASTEROID_RADIUS`, or 0 for asteroids not to pull each other.
+	pub asteroid_mass: f32,
+	pub integrator: Integrator,
+	pub aiming_time_scale: f32,
+	pub fast_forward_time_scale: f32,
+	pub slingshot_strength: f32,
+	pub slingshot_max_length: f32,
+	pub slingshot_pick_margin: f32,
+	pub slingshot_pick_lead: f32,
+	pub asteroid_spawn_period: f32,
+	pub asteroid_spawn_bias: f32,
+	pub asteroid_starvation_time: f32,
+	pub asteroid_speed_per_stage: f32,
+	pub spawn_rate_per_stage: f32,
+	pub spawn_wave_duration: f32,
+	pub spawn_lull_duration: f32,
+	pub spawn_burst_chance: f32,
+	pub spawn_burst_size: usize,
+	pub spawn_shower_chance: f32,
+	pub spawn_shower_size: usize,
+	pub spawn_shower_period: f32,
+	pub spawn_shower_spread: f32,
+	pub asteroid_collisions: AsteroidCollisions,
+	pub asteroid_restitution: f32,
+	pub asteroid_fragments: usize,
+	pub asteroid_fragment_speed: f32,
+	pub asteroid_min_shatter_radius: f32,
+	pub asteroid_min_ingredient_radius: f32,
+	pub asteroid_angular_velocity: Range<f32>,
+	pub asteroid_init_velocity_radial: Range<f32>,
+	pub asteroid_init_velocity_normal: Range<f32>,
+	pub screen_shake_intensity: f32,
+	pub screen_shake_speed: f32,
+	pub screen_shake_dampening: f32,
+	pub extinction_rate: f32,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+	Io(io::Error),
+	Parse(ron::Error),
+	NotPositive(&'static str),
+	Negative(&'static str),
+	NotProbability(&'static str),
+	TooSmall(&'static str, usize),
+	EmptyRange(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Io(err) => write!(f, "could not read file: {}", err),
+			ConfigError::Parse(err) => write!(f, "could not parse file: {}", err),
+			ConfigError::NotPositive(field) => write!(f, "`{}` must be positive", field),
+			ConfigError::Negative(field) => write!(f, "`{}` must not be negative", field),
+			ConfigError::NotProbability(field) => {
+				write!(f, "`{}` must be between 0 and 1", field)
+			}
+			ConfigError::TooSmall(field, min) => write!(f, "`{}` must be at least {}", field, min),
+			ConfigError::EmptyRange(field) => {
+				write!(f, "`{}` must have a start smaller than its end", field)
+			}
+		}
+	}
+}
+
+impl GameConfig {
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+		let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+		Self::from_ron(&contents)
+	}
+
+	pub fn from_ron(contents: &str) -> Result<Self, ConfigError> {
+		let config: Self = ron::de::from_str(contents).map_err(ConfigError::Parse)?;
+		config.validate()?;
+		Ok(config)
+	}
+
+	/// Checks the values that can't be expressed by their types.
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		let positive = [
+			("slingshot_max_length", self.slingshot_max_length),
+			("aiming_time_scale", self.aiming_time_scale),
+			("fast_forward_time_scale", self.fast_forward_time_scale),
+			("asteroid_spawn_period", self.asteroid_spawn_period),
+			("asteroid_starvation_time", self.asteroid_starvation_time),
+			("spawn_wave_duration", self.spawn_wave_duration),
+			("spawn_lull_duration", self.spawn_lull_duration),
+			("spawn_shower_period", self.spawn_shower_period),
+			(
+				"asteroid_min_shatter_radius",
+				self.asteroid_min_shatter_radius,
+			),
+			("screen_shake_dampening", self.screen_shake_dampening),
+			("extinction_rate", self.extinction_rate),
+		];
+		for &(field, value) in positive.iter() {
+			if value <= 0. {
+				return Err(ConfigError::NotPositive(field));
+			}
+		}
+
+		let non_negative = [
+			("slingshot_pick_margin", self.slingshot_pick_margin),
+			("slingshot_pick_lead", self.slingshot_pick_lead),
+			("asteroid_spawn_bias", self.asteroid_spawn_bias),
+			("asteroid_speed_per_stage", self.asteroid_speed_per_stage),
+			("spawn_rate_per_stage", self.spawn_rate_per_stage),
+			("spawn_shower_spread", self.spawn_shower_spread),
+			("asteroid_fragment_speed", self.asteroid_fragment_speed),
+			(
+				"asteroid_min_ingredient_radius",
+				self.asteroid_min_ingredient_radius,
+			),
+			("asteroid_mass", self.asteroid_mass),
+		];
+		for &(field, value) in non_negative.iter() {
+			if value < 0. {
+				return Err(ConfigError::Negative(field));
+			}
+		}
+
+		let probabilities = [
+			("spawn_burst_chance", self.spawn_burst_chance),
+			("spawn_shower_chance", self.spawn_shower_chance),
+			(
+				"spawn_burst_chance + spawn_shower_chance",
+				self.spawn_burst_chance + self.spawn_shower_chance,
+			),
+			("asteroid_restitution", self.asteroid_restitution),
+		];
+		for &(field, value) in probabilities.iter() {
+			if !(0. ..=1.).contains(&value) {
+				return Err(ConfigError::NotProbability(field));
+			}
+		}
+
+		if self.asteroid_fragments < 2 {
+			return Err(ConfigError::TooSmall("asteroid_fragments", 2));
+		}
+
+		let ranges = [
+			("asteroid_angular_velocity", &self.asteroid_angular_velocity),
+			(
+				"asteroid_init_velocity_radial",
+				&self.asteroid_init_velocity_radial,
+			),
+			(
+				"asteroid_init_velocity_normal",
+				&self.asteroid_init_velocity_normal,
+			),
+		];
+		for &(field, range) in ranges.iter() {
+			if range.start >= range.end {
+				return Err(ConfigError::EmptyRange(field));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// How long the screen keeps shaking after an impact.
+	pub fn screen_shake_duration(&self) -> f32 {
+		10. / self.screen_shake_dampening
+	}
+}
+
+pub struct ConfigWatcher {
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	timer: Timer,
+}
+
+impl ConfigWatcher {
+	pub fn new(path: PathBuf) -> Self {
+		let modified = last_modified(&path);
+		Self {
+			path,
+			modified,
+			timer: Timer::from_seconds(CONFIG_POLL_PERIOD, true),
+		}
+	}
+}
+
+fn last_modified(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.ok()
+}
+
+/// Loads the config file, and reloads it whenever it changes if `watch` is set.
+pub struct ConfigPlugin {
+	pub watch: bool,
+}
+
+impl Plugin for ConfigPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		let path = asset_path(CONFIG_PATH);
+		let config = GameConfig::load(&path).unwrap_or_else(|err| {
+			panic!("invalid config in `{}`: {}", CONFIG_PATH, err);
+		});
+
+		app.add_resource(config);
+		if self.watch {
+			app.add_resource(ConfigWatcher::new(path))
+				.add_system(watch_config.system());
+		}
+	}
+}
+
+/// Periodically checks whether the config file was modified, and reloads it if so.  An invalid
+/// file is reported and ignored, so that a typo doesn't crash a playtesting session.
+pub fn watch_config(
+	time: Res<Time>,
+	mut watcher: ResMut<ConfigWatcher>,
+	mut config: ResMut<GameConfig>,
+) {
+	if !watcher.timer.tick(time.delta_seconds()).just_finished() {
+		return;
+	}
+
+	let modified = last_modified(&watcher.path);
+	if modified == watcher.modified {
+		return;
+	}
+	watcher.modified = modified;
+
+	match GameConfig::load(&watcher.path) {
+		Ok(new_config) => {
+			*config = new_config;
+			info!("reloaded `{}`", CONFIG_PATH);
+		}
+		Err(err) => warn!("could not reload `{}`: {}", CONFIG_PATH, err),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SHIPPED: &str = include_str!("../assets/config.ron");
+
+	/// The shipped config with one field set to another value.
+	fn with(field: &str, value: &str) -> String {
+		let prefix = format!("{}:", field);
+		let mut replaced = false;
+		let contents = SHIPPED
+			.lines()
+			.map(|line| {
+				if line.trim_start().starts_with(&prefix) {
+					replaced = true;
+					format!("{} {},", prefix, value)
+				} else {
+					line.to_string()
+				}
+			})
+			.collect::<Vec<_>>()
+			.join("\n");
+		assert!(replaced, "no field `{}` in the shipped config", field);
+		contents
+	}
+
+	fn error_of(contents: &str) -> ConfigError {
+		match GameConfig::from_ron(contents) {
+			Ok(_) => panic!("expected the config to be rejected"),
+			Err(err) => err,
+		}
+	}
+
+	#[test]
+	fn loads_the_shipped_config() {
+		if let Err(err) = GameConfig::from_ron(SHIPPED) {
+			panic!("invalid shipped config: {}", err);
+		}
+	}
+
+	#[test]
+	fn rejects_values_that_are_not_positive() {
+		for field in [
+			"slingshot_max_length",
+			"aiming_time_scale",
+			"fast_forward_time_scale",
+			"asteroid_spawn_period",
+			"asteroid_starvation_time",
+			"spawn_wave_duration",
+			"spawn_lull_duration",
+			"spawn_shower_period",
+			"asteroid_min_shatter_radius",
+			"screen_shake_dampening",
+			"extinction_rate",
+		]
+		.iter()
+		{
+			for value in ["0.0", "-1.0"].iter() {
+				let err = error_of(&with(field, value));
+				assert!(matches!(err, ConfigError::NotPositive(name) if name == *field));
+			}
+		}
+	}
+
+	#[test]
+	fn rejects_negative_values() {
+		for field in [
+			"slingshot_pick_margin",
+			"slingshot_pick_lead",
+			"asteroid_spawn_bias",
+			"asteroid_speed_per_stage",
+			"spawn_rate_per_stage",
+			"spawn_shower_spread",
+			"asteroid_fragment_speed",
+			"asteroid_min_ingredient_radius",
+			"asteroid_mass",
+		]
+		.iter()
+		{
+			assert!(GameConfig::from_ron(&with(field, "0.0")).is_ok());
+			let err = error_of(&with(field, "-1.0"));
+			assert!(matches!(err, ConfigError::Negative(name) if name == *field));
+		}
+	}
+
+	#[test]
+	fn rejects_chances_that_are_not_probabilities() {
+		for field in [
+			"spawn_burst_chance",
+			"spawn_shower_chance",
+			"asteroid_restitution",
+		]
+		.iter()
+		{
+			for value in ["-0.1", "1.1"].iter() {
+				let err = error_of(&with(field, value));
+				assert!(matches!(err, ConfigError::NotProbability(name) if name == *field));
+			}
+		}
+
+		let contents = SHIPPED
+			.replace("spawn_burst_chance: 0.1,", "spawn_burst_chance: 0.6,")
+			.replace("spawn_shower_chance: 0.1,", "spawn_shower_chance: 0.6,");
+		let err = error_of(&contents);
+		assert!(matches!(err, ConfigError::NotProbability(name) if name.contains('+')));
+	}
+
+	#[test]
+	fn rejects_too_few_fragments() {
+		for value in ["0", "1"].iter() {
+			let err = error_of(&with("asteroid_fragments", value));
+			assert!(matches!(
+				err,
+				ConfigError::TooSmall("asteroid_fragments", 2)
+			));
+		}
+		assert!(GameConfig::from_ron(&with("asteroid_fragments", "2")).is_ok());
+	}
+
+	#[test]
+	fn rejects_empty_ranges() {
+		for field in [
+			"asteroid_angular_velocity",
+			"asteroid_init_velocity_radial",
+			"asteroid_init_velocity_normal",
+		]
+		.iter()
+		{
+			for value in ["(start: 1.0, end: 1.0)", "(start: 1.0, end: 0.5)"].iter() {
+				let err = error_of(&with(field, value));
+				assert!(matches!(err, ConfigError::EmptyRange(name) if name == *field));
+			}
+		}
+	}
+
+	#[test]
+	fn rejects_a_missing_field() {
+		let contents = SHIPPED.replace("gravity: 2e6,", "");
+		assert!(matches!(error_of(&contents), ConfigError::Parse(_)));
+	}
+}
@@ -0,0 +1,254 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::{
+	atlas::SpriteAtlas,
+	bodies::{PlanetSprite, StageChanged},
+	constants::EVOLUTION_DURATION,
+	current_recipe,
+	loading::GameAssets,
+	presentation::{spawn_explosion, sprite_sheet},
+	recipes::{Appearance, Recipes, Tint},
+	time_scale::TimeScale,
+	Biomass,
+};
+
+/// Color planets flash to halfway through evolving.
+const EVOLUTION_FLASH: Color = Color::WHITE;
+/// Color planets flash to halfway through going back a stage.
+const EXTINCTION_FLASH: Color = Color::rgb_linear(0.5, 0.1, 0.05);
+
+/// Draws planets the way their stage of evolution looks, and animates the changes from one stage
+/// to another.
+pub struct EvolutionPlugin;
+
+impl Plugin for EvolutionPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_system(attach_planet_sprites.system())
+			.add_system(start_transitions.system())
+			.add_system(animate_transitions.system());
+	}
+}
+
+/// Stage of evolution a planet is drawn at, and the ongoing change from the previous one.
+pub struct PlanetLook {
+	stage: usize,
+	transition: Option<Transition>,
+}
+
+struct Transition {
+	timer: Timer,
+	/// Tint of the planet when the transition started.
+	from: Color,
+	/// Whether the planet evolved, rather than going back a stage.
+	evolving: bool,
+}
+
+/// A layer drawn over a planet, as a child of it.
+pub struct PlanetOverlay {
+	tint: Color,
+	angular_velocity: f32,
+	/// Fading out, because the planet changed stage.
+	leaving: bool,
+}
+
+fn color(tint: Tint) -> Color {
+	Color::rgba(tint.0, tint.1, tint.2, tint.3)
+}
+
+fn mix(from: Color, to: Color, amount: f32) -> Color {
+	let mix = |from: f32, to: f32| from + (to - from) * amount;
+	Color::rgba(
+		mix(from.r(), to.r()),
+		mix(from.g(), to.g()),
+		mix(from.b(), to.b()),
+		mix(from.a(), to.a()),
+	)
+}
+
+fn with_alpha(color: Color, alpha: f32) -> Color {
+	Color::rgba(color.r(), color.g(), color.b(), alpha)
+}
+
+fn sprite_index(atlas: &SpriteAtlas, appearance: &Appearance, planet_sprite: &PlanetSprite) -> u32 {
+	atlas.sprite(appearance.sprite.as_ref().unwrap_or(&planet_sprite.0))
+}
+
+/// Spawns the overlays of an appearance over a planet, transparent if they are to fade in.
+fn spawn_overlays(
+	commands: &mut Commands,
+	game_assets: &GameAssets,
+	atlas: &SpriteAtlas,
+	planet: Entity,
+	appearance: &Appearance,
+	fade_in: bool,
+) {
+	for (index, overlay) in appearance.overlays.iter().enumerate() {
+		let mut transform =
+			Transform::from_translation(Vec3::new(0., 0., 0.1 * (index + 1) as f32));
+		transform.scale = Vec3::splat(overlay.scale);
+		let mut sprite_sheet = sprite_sheet(game_assets, atlas.sprite(&overlay.sprite), transform);
+		let tint = color(overlay.tint);
+		sprite_sheet.sprite.color = if fade_in { with_alpha(tint, 0.) } else { tint };
+		commands.spawn(sprite_sheet).with(PlanetOverlay {
+			tint,
+			angular_velocity: overlay.angular_velocity,
+			leaving: false,
+		});
+		let entity = commands.current_entity().unwrap();
+		commands.push_children(planet, &[entity]);
+	}
+}
+
+pub fn attach_planet_sprites(
+	commands: &mut Commands,
+	game_assets: Res<GameAssets>,
+	atlas: Res<SpriteAtlas>,
+	recipes: Res<Recipes>,
+	planets: Query<(Entity, &Transform, &PlanetSprite, &Biomass), Added<PlanetSprite>>,
+) {
+	for (entity, transform, planet_sprite, biomass) in planets.iter() {
+		let stage = current_recipe(&recipes, biomass.0);
+		let appearance = &recipes[stage].appearance;
+		let mut sprite_sheet = sprite_sheet(
+			&game_assets,
+			sprite_index(&atlas, appearance, planet_sprite),
+			*transform,
+		);
+		sprite_sheet.sprite.color = color(appearance.tint);
+		commands.insert(entity, sprite_sheet).insert_one(
+			entity,
+			PlanetLook {
+				stage,
+				transition: None,
+			},
+		);
+		spawn_overlays(commands, &game_assets, &atlas, entity, appearance, false);
+	}
+}
+
+/// Starts the transition of planets to their new stage, with a blast when they go back one.
+#[allow(clippy::too_many_arguments)]
+pub fn start_transitions(
+	commands: &mut Commands,
+	game_assets: Res<GameAssets>,
+	atlas: Res<SpriteAtlas>,
+	recipes: Res<Recipes>,
+	mut stage_reader: Local<EventReader<StageChanged>>,
+	stage_changes: Res<Events<StageChanged>>,
+	mut planets: Query<
+		(
+			&Transform,
+			&mut PlanetLook,
+			&TextureAtlasSprite,
+			Option<&Children>,
+		),
+		Without<PlanetOverlay>,
+	>,
+	mut overlays: Query<&mut PlanetOverlay>,
+) {
+	for change in stage_reader.iter(&stage_changes) {
+		let (transform, mut look, sprite, children) = match planets.get_mut(change.planet) {
+			Ok(planet) => planet,
+			// Not drawn yet, so it will look right from the start
+			Err(_) => continue,
+		};
+
+		let evolving = change.current > change.previous;
+		look.stage = change.current;
+		look.transition = Some(Transition {
+			timer: Timer::from_seconds(EVOLUTION_DURATION, false),
+			from: sprite.color,
+			evolving,
+		});
+
+		// The overlays of the previous stage fade out while the new ones fade in
+		for child in children.iter().flat_map(|children| children.iter()) {
+			if let Ok(mut overlay) = overlays.get_mut(*child) {
+				overlay.leaving = true;
+			}
+		}
+		spawn_overlays(
+			commands,
+			&game_assets,
+			&atlas,
+			change.planet,
+			&recipes[change.current].appearance,
+			true,
+		);
+
+		if !evolving {
+			let mut blast = Transform::from_translation(transform.translation + Vec3::unit_z());
+			blast.scale = transform.scale * 2.5;
+			spawn_explosion(commands, &game_assets, &atlas, blast);
+		}
+	}
+}
+
+pub fn animate_transitions(
+	commands: &mut Commands,
+	time: Res<Time>,
+	time_scale: Res<TimeScale>,
+	atlas: Res<SpriteAtlas>,
+	recipes: Res<Recipes>,
+	mut planets: Query<
+		(
+			&mut PlanetLook,
+			&PlanetSprite,
+			&mut TextureAtlasSprite,
+			Option<&Children>,
+		),
+		Without<PlanetOverlay>,
+	>,
+	mut overlays: Query<(&PlanetOverlay, &mut TextureAtlasSprite, &mut Transform)>,
+) {
+	let delta = time_scale.scale(time.delta_seconds());
+	for (mut look, planet_sprite, mut sprite, children) in planets.iter_mut() {
+		let appearance = &recipes[look.stage].appearance;
+		let target = color(appearance.tint);
+
+		// How far along the transition is, from 0 to 1
+		let progress = match &mut look.transition {
+			Some(transition) => {
+				transition.timer.tick(delta);
+				let progress = transition.timer.percent();
+				let flash = if transition.evolving {
+					EVOLUTION_FLASH
+				} else {
+					EXTINCTION_FLASH
+				};
+				let tint = mix(transition.from, target, progress);
+				sprite.color = mix(tint, flash, 0.6 * (PI * progress).sin());
+				// Swap sprites at the height of the flash
+				if progress >= 0.5 {
+					sprite.index = sprite_index(&atlas, appearance, planet_sprite);
+				}
+				progress
+			}
+			None => 1.,
+		};
+		let finished = progress >= 1.;
+		if finished && look.transition.is_some() {
+			look.transition = None;
+			sprite.color = target;
+			sprite.index = sprite_index(&atlas, appearance, planet_sprite);
+		}
+
+		for child in children.iter().flat_map(|children| children.iter()) {
+			if let Ok((overlay, mut overlay_sprite, mut transform)) = overlays.get_mut(*child) {
+				if overlay.leaving && finished {
+					commands.despawn_recursive(*child);
+					continue;
+				}
+				let visibility = if overlay.leaving {
+					1. - progress
+				} else {
+					progress
+				};
+				overlay_sprite.color = with_alpha(overlay.tint, overlay.tint.a() * visibility);
+				transform.rotate(Quat::from_rotation_z(overlay.angular_velocity * delta));
+			}
+		}
+	}
+}
@@ -1,17 +1,28 @@
-use bevy::prelude::{
-	AppBuilder, Assets, IntoSystem, Plugin, Res, ResMut, Resources, TextureAtlas, World,
+use bevy::{
+	app::{AppExit, Events},
+	prelude::{AppBuilder, Input, IntoSystem, KeyCode, Plugin, Query, Res, ResMut, State, With},
 };
 use bevy_egui::{
 	egui::{
-		self, widgets::Image, Align, Color32, FontDefinitions, FontFamily, Label, Layout, Pos2,
-		Rect, Stroke, TextStyle, TextureId, Ui, Vec2,
+		self, widgets::Image, Align, Color32, FontDefinitions, FontFamily, Label, Layout, Painter,
+		Pos2, Rect, Stroke, TextStyle, TextureId, Ui, Vec2,
 	},
 	EguiContext, EguiPlugin,
 };
 
 use crate::{
-	constants::{ASTEROID_SPRITES, DNA_SPRITE, EGUI_TEXTURE_ATLAS_ID, MENU_WIDTH, RECIPES},
-	current_recipe, Biomass, CurrentIngredients, Recipe,
+	accessibility::{pip_color, pips, ColorblindMode, Rgb, BADGE_SIZE, PIP_SIZE, PIP_SPACING},
+	atlas::SpriteAtlas,
+	audio::AudioSettings,
+	bodies::Planet,
+	constants::{ASTEROID_SPRITES, DNA_SPRITE, EGUI_TEXTURE_ATLAS_ID, MENU_WIDTH},
+	current_recipe,
+	level::{LevelTime, Levels},
+	loading::Loading,
+	recipes::{Recipe, Recipes},
+	save::SaveGame,
+	state::{AppState, NewGame},
+	Biomass, CurrentIngredients,
 };
 
 pub struct GuiPlugin;
@@ -20,18 +31,15 @@ impl Plugin for GuiPlugin {
 	fn build(&self, app: &mut AppBuilder) {
 		app.add_plugin(EguiPlugin)
 			.add_startup_system(setup_egui.system())
-			.add_system(side_panel.system());
+			.add_system(side_panel.system())
+			// Screens are drawn after the side panel, as egui requires
+			.add_system(screens.system())
+			.add_system(pause_on_escape.system());
 	}
 }
 
-pub fn setup_egui(_world: &mut World, resources: &mut Resources) {
-	let mut egui_ctx = resources.get_mut::<EguiContext>().unwrap();
-
-	let texture_atlases = resources.get::<Assets<TextureAtlas>>().unwrap();
-	let atlas = texture_atlases.iter().next().unwrap().1;
-
-	egui_ctx.set_egui_texture(EGUI_TEXTURE_ATLAS_ID, atlas.texture.clone());
-
+/// Sets up the looks of the GUI; sprites are only available to it once loaded.
+pub fn setup_egui(egui_ctx: ResMut<EguiContext>) {
 	let mut fonts = FontDefinitions::default();
 	fonts
 		.family_and_size
@@ -52,27 +60,52 @@ pub fn setup_egui(_world: &mut World, resources: &mut Resources) {
 	egui_ctx.ctx.set_visuals(visuals);
 }
 
-fn texture_atlas_uv(texture_atlas: &TextureAtlas, index: u32) -> Rect {
-	let size = texture_atlas.size;
-	let rect = texture_atlas.textures[index as usize];
+fn sprite_uv(atlas: &SpriteAtlas, sprite: &str) -> Rect {
+	let size = atlas.size;
+	let (min, max) = atlas.rects[atlas.sprite(sprite) as usize];
 	Rect::from_min_max(
-		Pos2::new(rect.min.x / size.x, rect.min.y / size.y),
-		Pos2::new(rect.max.x / size.x, rect.max.y / size.y),
+		Pos2::new(min.x / size.x, min.y / size.y),
+		Pos2::new(max.x / size.x, max.y / size.y),
 	)
 }
 
-fn image(texture_atlas: &TextureAtlas, index: u32, size: impl Into<Vec2>) -> Image {
-	Image::new(TextureId::User(EGUI_TEXTURE_ATLAS_ID), size)
-		.uv(texture_atlas_uv(texture_atlas, index))
+fn image(atlas: &SpriteAtlas, sprite: &str, size: impl Into<Vec2>) -> Image {
+	Image::new(TextureId::User(EGUI_TEXTURE_ATLAS_ID), size).uv(sprite_uv(atlas, sprite))
+}
+
+fn draw_dna(ui: &mut Ui, atlas: &SpriteAtlas, size: f32) -> egui::Response {
+	ui.add(image(atlas, DNA_SPRITE, [size, size]))
 }
 
-fn draw_dna(ui: &mut Ui, texture_atlas: &TextureAtlas, size: f32) -> egui::Response {
-	ui.add(image(texture_atlas, DNA_SPRITE, [size, size]))
+fn color(rgb: Rgb, alpha: u8) -> Color32 {
+	let channel = |value: f32| (value * 255.).round() as u8;
+	Color32::from_rgba_unmultiplied(channel(rgb.0), channel(rgb.1), channel(rgb.2), alpha)
+}
+
+/// Draws the badge of a kind of asteroid over its image, as it is drawn over asteroids.
+fn glyph(painter: &Painter, rect: Rect, palette: &[Rgb], kind: usize, alpha: u8) {
+	let width = rect.width();
+	let badge = palette[kind];
+	painter.rect_filled(
+		Rect::from_center_size(rect.center(), Vec2::splat(BADGE_SIZE * width)),
+		0.,
+		color(badge, alpha),
+	);
+	for (x, y) in pips(kind) {
+		// y points down in the GUI
+		let center = rect.center() + Vec2::new(*x, -*y) * PIP_SPACING * width;
+		painter.rect_filled(
+			Rect::from_center_size(center, Vec2::splat(PIP_SIZE * width)),
+			0.,
+			color(pip_color(badge), alpha),
+		);
+	}
 }
 
 fn recipe(
 	ui: &mut Ui,
-	texture_atlas: &TextureAtlas,
+	atlas: &SpriteAtlas,
+	colorblind_mode: ColorblindMode,
 	recipe: &Recipe,
 	current: bool,
 	current_ingredients: &[usize],
@@ -83,26 +116,31 @@ fn recipe(
 		..Default::default()
 	}
 	.show(ui, |ui| {
-		ui.label(recipe.name);
+		ui.label(recipe.name.as_str());
 		ui.horizontal_wrapped(|ui| {
 			ui.style_mut().spacing.item_spacing.x = 2.;
 			for (index, ingredient) in recipe.ingredients.iter().enumerate() {
 				for i in 0..*ingredient {
-					let mut image = image(texture_atlas, ASTEROID_SPRITES[index], [32., 32.]);
-					if !current || i >= current_ingredients[index] {
+					let mut image = image(atlas, ASTEROID_SPRITES[index], [32., 32.]);
+					let missing = !current || i >= current_ingredients[index];
+					if missing {
 						image = image.tint(Color32::from_white_alpha(127));
 					}
-					ui.add(image);
+					let rect = ui.add(image).rect;
+					if let Some(palette) = colorblind_mode.palette() {
+						let alpha = if missing { 127 } else { 255 };
+						glyph(ui.painter(), rect, palette, index, alpha);
+					}
 				}
 			}
 		});
 		ui.horizontal(|ui| {
 			ui.style_mut().spacing.item_spacing.x = 2.;
 			if current {
-				draw_dna(ui, texture_atlas, 20.0);
+				draw_dna(ui, atlas, 20.0);
 				ui.label(Label::new(format!("+{}", recipe.reward)).text_style(TextStyle::Small));
 			} else {
-				draw_dna(ui, texture_atlas, 20.0);
+				draw_dna(ui, atlas, 20.0);
 				ui.label(
 					Label::new(format!("≥{}", recipe.requirement)).text_style(TextStyle::Small),
 				);
@@ -111,49 +149,246 @@ fn recipe(
 	})
 }
 
+/// Biomass and recipes of a planet.
+fn planet(
+	ui: &mut Ui,
+	atlas: &SpriteAtlas,
+	colorblind_mode: ColorblindMode,
+	recipes: &Recipes,
+	title: &str,
+	biomass: &Biomass,
+	current_ingredients: &CurrentIngredients,
+) {
+	ui.with_layout(Layout::top_down(Align::Max), |ui| {
+		ui.style_mut().spacing.item_spacing.y = 2.;
+
+		ui.label(title);
+
+		ui.horizontal(|ui| {
+			ui.style_mut().spacing.item_spacing.x = 2.;
+
+			draw_dna(ui, atlas, 32.0);
+			ui.label(Label::new(format!("{}", biomass.0)).text_style(TextStyle::Heading));
+		});
+	});
+
+	ui.with_layout(Layout::top_down(Align::Max), |ui| {
+		ui.style_mut().spacing.item_spacing.y = 8.;
+
+		ui.label(Label::new("Current:").text_style(TextStyle::Small));
+		let index = current_recipe(recipes, biomass.0);
+		recipe(
+			ui,
+			atlas,
+			colorblind_mode,
+			&recipes[index],
+			true,
+			&current_ingredients.0,
+		);
+		if index + 1 < recipes.len() {
+			ui.label(Label::new("Next:").text_style(TextStyle::Small));
+			recipe(ui, atlas, colorblind_mode, &recipes[index + 1], false, &[]);
+		}
+	});
+}
+
+fn volume_sliders(ui: &mut Ui, settings: &mut AudioSettings) {
+	ui.add(egui::Slider::f32(&mut settings.master, 0.0..=1.0).text("Volume"));
+	ui.add(egui::Slider::f32(&mut settings.music, 0.0..=1.0).text("Music"));
+	ui.add(egui::Slider::f32(&mut settings.effects, 0.0..=1.0).text("Effects"));
+}
+
+/// Seconds as minutes and seconds, e.g. `1:05`.
+fn format_time(seconds: f32) -> String {
+	let seconds = seconds.ceil() as u32;
+	format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn side_panel(
 	mut egui_context: ResMut<EguiContext>,
-	texture_atlases: Res<Assets<TextureAtlas>>,
-	biomass: Res<Biomass>,
-	current_ingredients: Res<CurrentIngredients>,
+	atlas: Res<SpriteAtlas>,
+	recipes: Res<Recipes>,
+	levels: Res<Levels>,
+	level_time: Res<LevelTime>,
+	colorblind_mode: Res<ColorblindMode>,
+	state: Res<State<AppState>>,
+	planets: Query<(&Biomass, &CurrentIngredients), With<Planet>>,
 ) {
+	// The sprites it shows aren't there yet
+	if *state.current() == AppState::Loading {
+		return;
+	}
+
 	let ctx = &mut egui_context.ctx;
-	let texture_atlas = texture_atlases.iter().next().unwrap().1;
+	let several = planets.iter().count() > 1;
 
 	egui::SidePanel::left("side_panel", MENU_WIDTH).show(ctx, |ui| {
-		ui.vertical(|ui| {
-			ui.style_mut().spacing.item_spacing.y = 32.;
-
-			ui.with_layout(Layout::top_down(Align::Max), |ui| {
-				ui.style_mut().spacing.item_spacing.y = 2.;
-
-				ui.label("Biomass");
+		// Several planets don't fit on the screen
+		egui::ScrollArea::auto_sized().show(ui, |ui| {
+			ui.vertical(|ui| {
+				ui.style_mut().spacing.item_spacing.y = 32.;
 
-				ui.horizontal(|ui| {
-					ui.style_mut().spacing.item_spacing.x = 2.;
+				ui.with_layout(Layout::top_down(Align::Max), |ui| {
+					ui.style_mut().spacing.item_spacing.y = 2.;
 
-					draw_dna(ui, texture_atlas, 32.0);
-					ui.label(Label::new(format!("{}", biomass.0)).text_style(TextStyle::Heading));
+					let level = levels.current();
+					ui.label(Label::new(level.name.as_str()).text_style(TextStyle::Small));
+					if let Some(left) = level_time.left(level) {
+						ui.label(format!("Time left: {}", format_time(left)));
+					}
 				});
-			});
 
-			ui.with_layout(Layout::top_down(Align::Max), |ui| {
-				ui.style_mut().spacing.item_spacing.y = 8.;
-
-				ui.label(Label::new("Current:").text_style(TextStyle::Small));
-				let index = current_recipe(biomass.0);
-				recipe(
-					ui,
-					texture_atlas,
-					&RECIPES[index],
-					true,
-					&current_ingredients.0,
-				);
-				if index + 1 < RECIPES.len() {
-					ui.label(Label::new("Next:").text_style(TextStyle::Small));
-					recipe(ui, texture_atlas, &RECIPES[index + 1], false, &[]);
+				for (index, (biomass, current_ingredients)) in planets.iter().enumerate() {
+					let title = if several {
+						format!("Planet {}", index + 1)
+					} else {
+						"Biomass".to_string()
+					};
+					planet(
+						ui,
+						&atlas,
+						*colorblind_mode,
+						&recipes,
+						&title,
+						biomass,
+						current_ingredients,
+					);
 				}
 			});
 		});
 	});
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn screens(
+	mut egui_context: ResMut<EguiContext>,
+	mut state: ResMut<State<AppState>>,
+	mut levels: ResMut<Levels>,
+	level_time: Res<LevelTime>,
+	loading: Res<Loading>,
+	mut colorblind_mode: ResMut<ColorblindMode>,
+	mut audio_settings: ResMut<AudioSettings>,
+	mut new_games: ResMut<Events<NewGame>>,
+	mut saves: ResMut<Events<SaveGame>>,
+	mut exits: ResMut<Events<AppExit>>,
+) {
+	let ctx = &mut egui_context.ctx;
+	let current = *state.current();
+	if current == AppState::Playing {
+		return;
+	}
+
+	egui::CentralPanel::default().show(ctx, |ui| {
+		ui.with_layout(Layout::top_down(Align::Center), |ui| {
+			ui.style_mut().spacing.item_spacing.y = 16.;
+
+			let out_of_time = matches!(level_time.left(levels.current()), Some(left) if left <= 0.);
+			let (title, message) = match current {
+				AppState::Loading => ("Biomass", loading.error.as_deref().unwrap_or("Loading…")),
+				AppState::MainMenu => (
+					"Biomass",
+					"Evolve life by feeding asteroids to your planet.",
+				),
+				AppState::LevelSelect => ("Levels", ""),
+				AppState::Paused => ("Paused", ""),
+				AppState::Victory => ("Victory!", "Your planet is home to sentient life."),
+				AppState::GameOver if out_of_time => ("Game over", "Time ran out."),
+				AppState::GameOver => ("Game over", "An extinction wiped out all life."),
+				AppState::Playing => unreachable!(),
+			};
+			ui.label(Label::new(title).text_style(TextStyle::Heading));
+			if !message.is_empty() {
+				ui.label(message);
+			}
+			// Nothing to do but wait, or fix the files
+			if current == AppState::Loading {
+				return;
+			}
+
+			let play = match current {
+				AppState::MainMenu => Some("Play"),
+				AppState::Paused => Some("Resume"),
+				AppState::Victory => Some("Keep playing"),
+				_ => None,
+			};
+			if let Some(play) = play {
+				if ui.button(play).clicked() {
+					let _ = state.set_next(AppState::Playing);
+				}
+			}
+
+			if current == AppState::LevelSelect {
+				let mut selected = None;
+				for (index, level) in levels.iter().enumerate() {
+					if ui
+						.button(format!("{}. {}", index + 1, level.name))
+						.clicked()
+					{
+						selected = Some(index);
+					}
+					if !level.description.is_empty() {
+						ui.label(
+							Label::new(level.description.as_str()).text_style(TextStyle::Small),
+						);
+					}
+				}
+				// Skipped when loading the levels, but listed for whoever edits them
+				for (path, err) in levels.invalid.iter() {
+					let file = path.file_name().unwrap_or_default().to_string_lossy();
+					ui.label(
+						Label::new(format!("`{}` can't be played: {}", file, err))
+							.text_style(TextStyle::Small),
+					);
+				}
+				if let Some(index) = selected {
+					levels.current = index;
+					new_games.send(NewGame);
+					let _ = state.set_next(AppState::Playing);
+				}
+			} else if current != AppState::Paused && ui.button("New game").clicked() {
+				new_games.send(NewGame);
+				let _ = state.set_next(AppState::Playing);
+			}
+
+			if current == AppState::MainMenu || current == AppState::Paused {
+				volume_sliders(ui, &mut audio_settings);
+			}
+
+			if current == AppState::MainMenu {
+				if ui.button("Levels").clicked() {
+					let _ = state.set_next(AppState::LevelSelect);
+				}
+				if ui
+					.button(format!("Colorblind mode: {}", *colorblind_mode))
+					.clicked()
+				{
+					*colorblind_mode = colorblind_mode.next();
+				}
+				if ui.button("Quit").clicked() {
+					saves.send(SaveGame);
+					exits.send(AppExit);
+				}
+			} else if ui.button("Main menu").clicked() {
+				let _ = state.set_next(AppState::MainMenu);
+			}
+		});
+	});
+}
+
+pub fn pause_on_escape(keyboard_input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+	if keyboard_input.just_pressed(KeyCode::Escape) {
+		match state.current() {
+			AppState::Playing => {
+				let _ = state.set_next(AppState::Paused);
+			}
+			AppState::Paused => {
+				let _ = state.set_next(AppState::Playing);
+			}
+			AppState::LevelSelect => {
+				let _ = state.set_next(AppState::MainMenu);
+			}
+			_ => {}
+		}
+	}
+}
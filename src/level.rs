@@ -0,0 +1,443 @@
+use std::{
+	fmt, fs, io,
+	ops::Range,
+	path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+	asset_path,
+	bodies::spawn_bodies,
+	constants::{LEVELS_PATH, PLANET_RADIUS, PLANET_SPRITE},
+	physics::{SimulationClock, FIXED_UPDATE},
+	recipes::Recipes,
+};
+
+/// A planet and the moons orbiting it.
+#[derive(Deserialize)]
+pub struct PlanetDescription {
+	pub position: (f32, f32),
+	pub radius: f32,
+	/// Multiple of `gravity`, by default according to the radius.
+	#[serde(default)]
+	pub mass: Option<f32>,
+	/// Name of the sprite in the atlas.
+	#[serde(default = "planet_sprite")]
+	pub sprite: String,
+	pub angular_velocity: f32,
+	#[serde(default)]
+	pub moons: Vec<MoonDescription>,
+}
+
+impl PlanetDescription {
+	pub fn mass(&self) -> f32 {
+		self.mass.unwrap_or_else(|| default_mass(self.radius))
+	}
+}
+
+#[derive(Deserialize)]
+pub struct MoonDescription {
+	/// From the center of the planet.
+	pub distance: f32,
+	pub radius: f32,
+	/// Multiple of `gravity`, by default according to the radius.
+	#[serde(default)]
+	pub mass: Option<f32>,
+	/// Seconds per revolution, counterclockwise.
+	pub period: f32,
+}
+
+impl MoonDescription {
+	pub fn mass(&self) -> f32 {
+		self.mass.unwrap_or_else(|| default_mass(self.radius))
+	}
+}
+
+fn planet_sprite() -> String {
+	PLANET_SPRITE.to_string()
+}
+
+/// Mass of a body as dense as a planet of mass 1 and radius `PLANET_RADIUS`.
+fn default_mass(radius: f32) -> f32 {
+	(radius / PLANET_RADIUS).powi(2)
+}
+
+/// An obstacle pulling asteroids towards it, or pushing them away with a negative mass, and
+/// destroying the ones that reach it.
+#[derive(Deserialize)]
+pub struct GravityWellDescription {
+	pub position: (f32, f32),
+	pub radius: f32,
+	/// Multiple of `gravity`.
+	pub mass: f32,
+}
+
+/// A level as it is written in its file, with the goal given by the name of a recipe.
+#[derive(Deserialize)]
+struct LevelDescription {
+	name: String,
+	#[serde(default)]
+	description: String,
+	planets: Vec<PlanetDescription>,
+	#[serde(default)]
+	gravity_wells: Vec<GravityWellDescription>,
+	#[serde(default)]
+	spawn_zones: Vec<Range<f32>>,
+	#[serde(default)]
+	goal: Option<String>,
+	#[serde(default)]
+	time_limit: Option<f32>,
+}
+
+/// Where the game takes place, and what it takes to win it.
+pub struct Level {
+	pub name: String,
+	pub description: String,
+	pub planets: Vec<PlanetDescription>,
+	pub gravity_wells: Vec<GravityWellDescription>,
+	/// Ranges of angles, in radians, from which asteroids come; from everywhere if empty.
+	pub spawn_zones: Vec<Range<f32>>,
+	/// Index of the recipe every planet must reach to win.
+	pub goal: usize,
+	/// Seconds of simulation to reach the goal in, if limited.
+	pub time_limit: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+	Io(io::Error),
+	Parse(ron::Error),
+	NoPlanets,
+	NotPositive(&'static str),
+	EmptyRange(&'static str),
+	UnknownRecipe(String),
+}
+
+impl fmt::Display for LevelError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			LevelError::Io(err) => write!(f, "could not read file: {}", err),
+			LevelError::Parse(err) => write!(f, "could not parse file: {}", err),
+			LevelError::NoPlanets => write!(f, "there must be at least one planet"),
+			LevelError::NotPositive(field) => write!(f, "`{}` must be positive", field),
+			LevelError::EmptyRange(field) => {
+				write!(f, "`{}` must have a start smaller than its end", field)
+			}
+			LevelError::UnknownRecipe(name) => write!(f, "there is no recipe named `{}`", name),
+		}
+	}
+}
+
+impl Level {
+	pub fn load(path: impl AsRef<Path>, recipes: &Recipes) -> Result<Self, LevelError> {
+		let contents = fs::read_to_string(path).map_err(LevelError::Io)?;
+		Self::from_ron(&contents, recipes)
+	}
+
+	pub fn from_ron(contents: &str, recipes: &Recipes) -> Result<Self, LevelError> {
+		let level: LevelDescription = ron::de::from_str(contents).map_err(LevelError::Parse)?;
+
+		if level.planets.is_empty() {
+			return Err(LevelError::NoPlanets);
+		}
+
+		let mut positive = vec![];
+		for planet in level.planets.iter() {
+			positive.push(("planets.radius", planet.radius));
+			positive.push(("planets.mass", planet.mass()));
+			for moon in planet.moons.iter() {
+				positive.push(("planets.moons.distance", moon.distance));
+				positive.push(("planets.moons.radius", moon.radius));
+				positive.push(("planets.moons.mass", moon.mass()));
+				positive.push(("planets.moons.period", moon.period));
+			}
+		}
+		for well in level.gravity_wells.iter() {
+			positive.push(("gravity_wells.radius", well.radius));
+		}
+		if let Some(time_limit) = level.time_limit {
+			positive.push(("time_limit", time_limit));
+		}
+		for &(field, value) in positive.iter() {
+			if value <= 0. {
+				return Err(LevelError::NotPositive(field));
+			}
+		}
+
+		if level.spawn_zones.iter().any(|zone| zone.start >= zone.end) {
+			return Err(LevelError::EmptyRange("spawn_zones"));
+		}
+
+		let goal = match level.goal {
+			Some(name) => recipes
+				.0
+				.iter()
+				.position(|recipe| recipe.name == name)
+				.ok_or(LevelError::UnknownRecipe(name))?,
+			None => recipes.len() - 1,
+		};
+
+		Ok(Self {
+			name: level.name,
+			description: level.description,
+			planets: level.planets,
+			gravity_wells: level.gravity_wells,
+			spawn_zones: level.spawn_zones,
+			goal,
+			time_limit: level.time_limit,
+		})
+	}
+}
+
+/// Every level, in the order they are played, and the one being played.
+pub struct Levels {
+	levels: Vec<Level>,
+	/// Files of the levels that could not be loaded, and why.
+	pub invalid: Vec<(PathBuf, LevelError)>,
+	pub current: usize,
+}
+
+impl Levels {
+	pub fn current(&self) -> &Level {
+		&self.levels[self.current]
+	}
+
+	pub fn current_mut(&mut self) -> &mut Level {
+		&mut self.levels[self.current]
+	}
+
+	pub fn len(&self) -> usize {
+		self.levels.len()
+	}
+
+	/// Never true, since there must be at least the level to start with.
+	pub fn is_empty(&self) -> bool {
+		self.levels.is_empty()
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = &Level> {
+		self.levels.iter()
+	}
+}
+
+/// Level files in a folder, sorted by name.
+fn level_paths(folder: &Path) -> io::Result<Vec<PathBuf>> {
+	let mut paths = vec![];
+	for entry in fs::read_dir(folder)? {
+		let path = entry?.path();
+		if path.extension() == Some("ron".as_ref()) {
+			paths.push(path);
+		}
+	}
+	paths.sort();
+	Ok(paths)
+}
+
+/// Seconds of simulation since the level started.
+#[derive(Default)]
+pub struct LevelTime(pub f32);
+
+impl LevelTime {
+	/// Seconds left to reach the goal of the level, if limited.
+	pub fn left(&self, level: &Level) -> Option<f32> {
+		level
+			.time_limit
+			.map(|time_limit| (time_limit - self.0).max(0.))
+	}
+}
+
+/// Loads the levels from `assets/levels`, and sets up the one to start with.
+///
+/// Needs the recipes, which levels refer to by name.
+pub struct LevelPlugin {
+	/// Index of the level to start with.
+	pub level: usize,
+}
+
+impl Plugin for LevelPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		let mut levels = vec![];
+		let mut invalid = vec![];
+		{
+			let recipes = app.resources().get::<Recipes>().unwrap();
+			let paths = level_paths(&asset_path(LEVELS_PATH)).unwrap_or_else(|err| {
+				panic!("could not list the levels in `{}`: {}", LEVELS_PATH, err);
+			});
+			// Skip the invalid levels, which the level select screen lists with their error
+			for path in paths {
+				match Level::load(&path, &recipes) {
+					Ok(level) => levels.push(level),
+					Err(err) => {
+						warn!("invalid level `{}`: {}", path.display(), err);
+						invalid.push((path, err));
+					}
+				}
+			}
+		}
+		if self.level >= levels.len() {
+			panic!(
+				"there is no level {}, only {} valid in `{}`",
+				self.level + 1,
+				levels.len(),
+				LEVELS_PATH
+			);
+		}
+
+		app.add_resource(Levels {
+			levels,
+			invalid,
+			current: self.level,
+		})
+		.add_resource(LevelTime::default())
+		.add_startup_system(setup_level.system())
+		.add_system_to_stage(FIXED_UPDATE, advance_level_time.system());
+	}
+}
+
+pub fn setup_level(commands: &mut Commands, levels: Res<Levels>) {
+	spawn_bodies(commands, levels.current());
+}
+
+pub fn advance_level_time(clock: Res<SimulationClock>, mut level_time: ResMut<LevelTime>) {
+	level_time.0 += clock.delta();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn recipes() -> Recipes {
+		Recipes::from_ron(
+			r#"[
+				(name: "First", requirement: 0, reward: 10, ingredients: {"teal": 1}),
+				(name: "Second", requirement: 40, reward: 20, ingredients: {"blue": 1}),
+			]"#,
+		)
+		.unwrap()
+	}
+
+	/// A level with the given planets, and other fields, e.g. `goal: Some("First"),`.
+	fn level(planets: &str, fields: &str) -> String {
+		format!("(name: \"Test\", planets: [{}], {})", planets, fields)
+	}
+
+	const PLANET: &str = "(position: (0.0, 0.0), radius: 100.0, angular_velocity: 0.1)";
+
+	fn error_of(contents: &str) -> LevelError {
+		match Level::from_ron(contents, &recipes()) {
+			Ok(_) => panic!("expected the level to be rejected"),
+			Err(err) => err,
+		}
+	}
+
+	#[test]
+	fn loads_the_shipped_levels() {
+		let recipes = Recipes::load(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/recipes.ron"))
+			.unwrap_or_else(|err| panic!("invalid shipped recipes: {}", err));
+		let folder = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/levels"));
+		let paths = level_paths(folder).unwrap();
+		assert!(!paths.is_empty());
+		for path in paths {
+			if let Err(err) = Level::load(&path, &recipes) {
+				panic!("invalid shipped level `{}`: {}", path.display(), err);
+			}
+		}
+	}
+
+	#[test]
+	fn goal_defaults_to_the_last_recipe() {
+		let last = Level::from_ron(&level(PLANET, ""), &recipes()).unwrap();
+		assert_eq!(last.goal, 1);
+		let first = Level::from_ron(&level(PLANET, "goal: Some(\"First\"),"), &recipes()).unwrap();
+		assert_eq!(first.goal, 0);
+	}
+
+	#[test]
+	fn rejects_a_level_without_planets() {
+		assert!(matches!(error_of(&level("", "")), LevelError::NoPlanets));
+	}
+
+	#[test]
+	fn rejects_planets_and_moons_that_are_not_positive() {
+		let planet = |radius, mass, moon: &str| {
+			format!(
+				"(position: (0.0, 0.0), radius: {}, mass: Some({}), angular_velocity: 0.1, \
+				 moons: [{}])",
+				radius, mass, moon
+			)
+		};
+		let moon = |distance, radius, mass, period| {
+			format!(
+				"(distance: {}, radius: {}, mass: Some({}), period: {})",
+				distance, radius, mass, period
+			)
+		};
+		let valid_moon = moon("300.0", "20.0", "0.1", "20.0");
+		let valid = level(&planet("100.0", "1.0", &valid_moon), "");
+		assert!(Level::from_ron(&valid, &recipes()).is_ok());
+
+		let cases = [
+			("planets.radius", planet("0.0", "1.0", "")),
+			("planets.mass", planet("100.0", "-1.0", "")),
+			(
+				"planets.moons.distance",
+				planet("100.0", "1.0", &moon("0.0", "20.0", "0.1", "20.0")),
+			),
+			(
+				"planets.moons.radius",
+				planet("100.0", "1.0", &moon("300.0", "-1.0", "0.1", "20.0")),
+			),
+			(
+				"planets.moons.mass",
+				planet("100.0", "1.0", &moon("300.0", "20.0", "0.0", "20.0")),
+			),
+			(
+				"planets.moons.period",
+				planet("100.0", "1.0", &moon("300.0", "20.0", "0.1", "0.0")),
+			),
+		];
+		for (field, planets) in cases.iter() {
+			let err = error_of(&level(planets, ""));
+			assert!(matches!(err, LevelError::NotPositive(name) if name == *field));
+		}
+	}
+
+	#[test]
+	fn rejects_gravity_wells_and_time_limits_that_are_not_positive() {
+		let wells = "gravity_wells: [(position: (400.0, 200.0), radius: 0.0, mass: -0.5)],";
+		let err = error_of(&level(PLANET, wells));
+		assert!(matches!(
+			err,
+			LevelError::NotPositive("gravity_wells.radius")
+		));
+
+		let err = error_of(&level(PLANET, "time_limit: Some(0.0),"));
+		assert!(matches!(err, LevelError::NotPositive("time_limit")));
+	}
+
+	#[test]
+	fn rejects_empty_spawn_zones() {
+		for zones in ["(start: 1.0, end: 1.0)", "(start: 1.0, end: 0.5)"].iter() {
+			let err = error_of(&level(PLANET, &format!("spawn_zones: [{}],", zones)));
+			assert!(matches!(err, LevelError::EmptyRange("spawn_zones")));
+		}
+	}
+
+	#[test]
+	fn rejects_an_unknown_goal() {
+		let err = error_of(&level(PLANET, "goal: Some(\"Third\"),"));
+		assert!(matches!(err, LevelError::UnknownRecipe(name) if name == "Third"));
+	}
+
+	#[test]
+	fn masses_default_to_the_area() {
+		let planets = "(position: (0.0, 0.0), radius: 50.0, angular_velocity: 0.1, moons: [\
+			(distance: 300.0, radius: 20.0, period: 20.0)])";
+		let level = Level::from_ron(&level(planets, ""), &recipes()).unwrap();
+		let planet = &level.planets[0];
+		assert_eq!(planet.mass(), 0.25);
+		assert!((planet.moons[0].mass() - 0.04).abs() < 1e-6);
+	}
+}
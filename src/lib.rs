@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+
+pub mod accessibility;
+pub mod atlas;
+pub mod audio;
+pub mod bodies;
+pub mod collisions;
+pub mod config;
+pub mod constants;
+pub mod evolution;
+pub mod gui;
+pub mod level;
+pub mod loading;
+pub mod physics;
+pub mod presentation;
+pub mod recipes;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod screen_shaker;
+pub mod simulation;
+pub mod slingshot;
+pub mod spatial;
+pub mod spawning;
+pub mod state;
+pub mod stress;
+pub mod time_scale;
+pub mod trajectory;
+
+use constants::N_ASTEROIDS;
+use recipes::{Ingredients, Recipes};
+
+pub struct Biomass(pub u32);
+
+#[derive(Default)]
+pub struct CurrentIngredients(Ingredients);
+
+impl CurrentIngredients {
+	pub fn new() -> Self {
+		Self([0; N_ASTEROIDS])
+	}
+}
+
+pub fn current_recipe(recipes: &Recipes, biomass: u32) -> usize {
+	for (i, recipe) in recipes.0.iter().enumerate().rev() {
+		if biomass >= recipe.requirement {
+			return i;
+		}
+	}
+	panic!("there are no recipes for {} biomass", biomass);
+}
+
+/// Path of a file in the `assets` folder, resolved the same way Bevy's asset server does.
+pub fn asset_path(path: &str) -> PathBuf {
+	let root = match std::env::var("CARGO_MANIFEST_DIR") {
+		Ok(manifest_dir) => PathBuf::from(manifest_dir),
+		Err(_) => std::env::current_exe()
+			.ok()
+			.and_then(|exe| exe.parent().map(Path::to_path_buf))
+			.unwrap_or_default(),
+	};
+	root.join("assets").join(path)
+}
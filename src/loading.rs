@@ -0,0 +1,96 @@
+use bevy::{
+	asset::{HandleId, LoadState},
+	prelude::*,
+};
+
+use crate::{
+	atlas::SpriteAtlas,
+	audio::Sounds,
+	constants::ATLAS_PATH,
+	state::{AppState, APP_STATE_STAGE},
+};
+
+/// Handles to everything the game draws and plays, by what it is used for.
+pub struct GameAssets {
+	/// Where the sprites are in the texture, reloaded whenever its file changes.
+	pub atlas: Handle<SpriteAtlas>,
+	/// Sprites cut from the texture, set once the atlas is loaded.
+	pub sprites: Handle<TextureAtlas>,
+	pub sounds: Sounds,
+}
+
+impl GameAssets {
+	/// Assets read from files, which the game waits for before starting.
+	///
+	/// The texture is only known once the atlas is loaded.
+	#[allow(clippy::wrong_self_convention)]
+	fn from_files(&self, atlases: &Assets<SpriteAtlas>) -> Vec<HandleId> {
+		let mut files = vec![self.atlas.id];
+		if let Some(atlas) = atlases.get(&self.atlas) {
+			files.push(HandleId::from(atlas.texture.as_str()));
+		}
+		files.extend(self.sounds.handles().map(|handle| handle.id));
+		files
+	}
+}
+
+/// How loading is going, shown on the loading screen.
+#[derive(Default)]
+pub struct Loading {
+	/// Whether the atlas was checked against the recipes and levels, and is in use.
+	pub atlas_ready: bool,
+	/// Why the game can't start.
+	pub error: Option<String>,
+}
+
+/// Loads the assets of the game, and leaves the loading screen once they are ready.
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.init_resource::<Loading>()
+			.add_startup_system(load_assets.system())
+			.on_state_update(APP_STATE_STAGE, AppState::Loading, finish_loading.system());
+	}
+}
+
+pub fn load_assets(
+	commands: &mut Commands,
+	asset_server: Res<AssetServer>,
+	texture_atlases: Res<Assets<TextureAtlas>>,
+) {
+	if let Err(err) = asset_server.watch_for_changes() {
+		warn!("could not watch assets for changes: {:?}", err);
+	}
+	commands.insert_resource(GameAssets {
+		atlas: asset_server.load(ATLAS_PATH),
+		sprites: texture_atlases.get_handle(HandleId::random::<TextureAtlas>()),
+		sounds: Sounds::load(&asset_server),
+	});
+}
+
+/// Goes to the main menu once every file is loaded, or shows which one could not be.
+pub fn finish_loading(
+	asset_server: Res<AssetServer>,
+	atlases: Res<Assets<SpriteAtlas>>,
+	game_assets: Res<GameAssets>,
+	mut loading: ResMut<Loading>,
+	mut state: ResMut<State<AppState>>,
+) {
+	let files = game_assets.from_files(&atlases);
+	match asset_server.get_group_load_state(files.iter().copied()) {
+		LoadState::Loaded if loading.atlas_ready => {
+			let _ = state.set_next(AppState::MainMenu);
+		}
+		LoadState::Failed => {
+			let failed = files
+				.into_iter()
+				.find(|file| asset_server.get_load_state(*file) == LoadState::Failed)
+				.and_then(|file| asset_server.get_handle_path(file));
+			if let Some(path) = failed {
+				loading.error = Some(format!("could not load `{}`", path.path().display()));
+			}
+		}
+		_ => {}
+	}
+}
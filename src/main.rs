@@ -1,160 +1,119 @@
-use bevy::{prelude::*, render::pass::ClearColor};
+use std::path::PathBuf;
 
-mod bodies;
-mod constants;
-mod gui;
-mod physics;
-mod screen_shaker;
-mod slingshot;
-
-use bodies::{AsteroidSpawerTimer, Planet, Radius};
-use constants::{
-	ASTEROID_SPAWN_PERIOD, INITIAL_BIOMASS, MENU_WIDTH, N_ASTEROIDS, RECIPES, SCREEN_HEIGHT,
-	SCREEN_WIDTH, SPRITES_BOUNDARIES, VIEWPORT_SCALE,
+use bevy::{log::LogPlugin, prelude::*};
+use biomass_rs::{
+	accessibility::ColorblindMode, constants::SAVE_PATH, presentation::PresentationPlugin,
+	simulation::SimulationPlugin, stress::StressPlugin,
 };
-use physics::AngularVelocity;
-use screen_shaker::ScreenShakeBundle;
-
-fn setup(
-	commands: &mut Commands,
-	asset_server: Res<AssetServer>,
-	mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-) {
-	let texture_handle = asset_server.load("images/textures.png");
-	let mut texture_atlas = TextureAtlas::new_empty(texture_handle, Vec2::new(800., 600.));
-
-	for (min, max) in SPRITES_BOUNDARIES.iter() {
-		texture_atlas.add_texture(bevy::sprite::Rect {
-			min: (*min).into(),
-			max: (*max).into(),
-		});
-	}
-
-	let texture_atlas_handle = texture_atlases.add(texture_atlas);
-
-	commands
-		.insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
-		.insert_resource(WindowDescriptor {
-			title: "Biomass".to_string(),
-			width: SCREEN_WIDTH,
-			height: SCREEN_HEIGHT,
-			resizable: false,
-			vsync: true,
-			..Default::default()
-		})
-		// Screen shaker
-		.spawn(ScreenShakeBundle::default())
-		.with_children(|parent| {
-			// Camera
-			parent.spawn(Camera2dBundle {
-				transform: Transform::from_translation(Vec3::new(-MENU_WIDTH, 0., 0.))
-					* Transform::from_scale(Vec3::splat(1. / VIEWPORT_SCALE)),
-				..Default::default()
-			});
-		})
-		// Planet
-		.spawn(SpriteSheetBundle {
-			texture_atlas: texture_atlas_handle.clone(),
-			sprite: TextureAtlasSprite::new(0),
-			transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
-			..Default::default()
-		})
-		.with(AngularVelocity(0.1))
-		.with(Radius(100.))
-		.with(Planet);
-}
-
-fn main() {
-	App::build()
-		.add_plugins(DefaultPlugins)
-		.add_startup_system(setup.system())
-		// GUI
-		.add_plugin(gui::GuiPlugin)
-		// Physics & animations
-		.add_plugin(physics::PhysicsPlugin)
-		// Asteroid stuff
-		.add_system(bodies::collision.system())
-		.add_system(bodies::animate_explosion.system())
-		.add_resource(AsteroidSpawerTimer(Timer::from_seconds(
-			ASTEROID_SPAWN_PERIOD,
-			true,
-		)))
-		.add_system(bodies::asteroid_spawner.system())
-		.add_system(bodies::asteroid_despawner.system())
-		// Slingshot
-		.add_plugin(slingshot::SlingshotPlugin)
-		// Screen shake
-		.add_plugin(screen_shaker::ScreenShakePlugin)
-		// Biomass stuff
-		.add_resource(Biomass(INITIAL_BIOMASS))
-		.add_resource(CurrentIngredients::new())
-		.run();
-}
-
-type Ingredients = [usize; N_ASTEROIDS];
-
-pub fn add_ingredient(ingredients: &Ingredients, index: usize) -> Ingredients {
-	let mut new = ingredients.clone();
-	new[index] += 1;
-	new
-}
-
-pub struct Recipe {
-	pub name: &'static str,
-	pub requirement: u32,
-	pub reward: u32,
-	pub ingredients: Ingredients,
-}
 
-pub enum IngredientsComparison {
-	TooFew,
-	TooMany,
-	JustRight,
+/// Command line options.
+#[derive(Default)]
+struct Options {
+	headless: bool,
+	seed: Option<u64>,
+	/// Index of the level to start with.
+	level: Option<usize>,
+	record: Option<PathBuf>,
+	replay: Option<PathBuf>,
+	stress: Option<usize>,
+	colorblind_mode: ColorblindMode,
 }
 
-impl Recipe {
-	pub const fn new(
-		name: &'static str,
-		requirement: u32,
-		reward: u32,
-		ingredients: Ingredients,
-	) -> Self {
-		Self {
-			name,
-			requirement,
-			reward,
-			ingredients,
-		}
-	}
-
-	pub fn check_ingredients(&self, ingredients: &Ingredients) -> IngredientsComparison {
-		let mut result = IngredientsComparison::JustRight;
-		for (i, j) in self.ingredients.iter().zip(ingredients) {
-			if j < i {
-				result = IngredientsComparison::TooFew;
-			} else if j > i {
-				return IngredientsComparison::TooMany;
+impl Options {
+	fn from_args() -> Result<Self, String> {
+		let mut options = Self::default();
+		let mut args = std::env::args().skip(1);
+		while let Some(arg) = args.next() {
+			match arg.as_str() {
+				"--headless" => options.headless = true,
+				"--seed" => {
+					let seed = args.next().ok_or("`--seed` expects a value")?;
+					let seed = seed
+						.parse()
+						.map_err(|_| format!("invalid seed `{}`", seed))?;
+					options.seed = Some(seed);
+				}
+				"--level" => {
+					let level = args.next().ok_or("`--level` expects a value")?;
+					let number: usize = level
+						.parse()
+						.ok()
+						.filter(|number| *number > 0)
+						.ok_or_else(|| format!("invalid level `{}`", level))?;
+					options.level = Some(number - 1);
+				}
+				"--record" => {
+					let path = args.next().ok_or("`--record` expects a file")?;
+					options.record = Some(path.into());
+				}
+				"--replay" => {
+					let path = args.next().ok_or("`--replay` expects a file")?;
+					options.replay = Some(path.into());
+				}
+				"--stress" => {
+					let count = args.next().ok_or("`--stress` expects a value")?;
+					let count = count
+						.parse()
+						.map_err(|_| format!("invalid number of asteroids `{}`", count))?;
+					options.stress = Some(count);
+				}
+				"--colorblind" => {
+					let mode = args.next().ok_or("`--colorblind` expects a mode")?;
+					options.colorblind_mode = ColorblindMode::from_name(&mode)
+						.ok_or_else(|| format!("invalid colorblind mode `{}`", mode))?;
+				}
+				_ => return Err(format!("unknown option `{}`", arg)),
 			}
 		}
-		return result;
+		Ok(options)
 	}
 }
 
-pub struct Biomass(u32);
-
-pub struct CurrentIngredients(Ingredients);
-
-impl CurrentIngredients {
-	pub fn new() -> Self {
-		Self([0; N_ASTEROIDS])
+fn main() {
+	let options = Options::from_args().unwrap_or_else(|err| {
+		eprintln!("error: {}", err);
+		eprintln!(
+			"usage: biomass-rs [--headless] [--seed <number>] [--level <number>] [--record <file>] \
+			 [--replay <file>] [--stress <asteroids>] \
+			 [--colorblind <off|deuteranopia|protanopia|tritanopia>]"
+		);
+		std::process::exit(1);
+	});
+
+	// Recorded and replayed runs start from scratch, and so do the headless game and stress tests
+	let save = if options.headless
+		|| options.record.is_some()
+		|| options.replay.is_some()
+		|| options.stress.is_some()
+	{
+		None
+	} else {
+		Some(PathBuf::from(SAVE_PATH))
+	};
+
+	let simulation = SimulationPlugin {
+		seed: options.seed,
+		level: options.level,
+		record: options.record,
+		replay: options.replay,
+		save,
+	};
+
+	let mut app = App::build();
+	if options.headless {
+		app.add_plugins(MinimalPlugins)
+			// Before the simulation, which logs the seed as it is built
+			.add_plugin(LogPlugin)
+			.add_plugin(simulation);
+	} else {
+		app.add_plugins(DefaultPlugins)
+			.add_plugin(simulation)
+			.add_plugin(PresentationPlugin {
+				colorblind_mode: options.colorblind_mode,
+			});
 	}
-}
-
-pub fn current_recipe(biomass: u32) -> usize {
-	for (i, recipe) in RECIPES.iter().enumerate().rev() {
-		if biomass >= recipe.requirement {
-			return i;
-		}
+	if let Some(asteroids) = options.stress {
+		app.add_plugin(StressPlugin { asteroids });
 	}
-	panic!("there are no recipes for {} biomass", biomass);
+	app.run();
 }
@@ -1,70 +1,515 @@
-use bevy::{
-	core::{FixedTimestep, FixedTimesteps},
-	prelude::*,
-};
+use std::f32::consts::TAU;
+
+use bevy::{ecs::ShouldRun, prelude::*};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-	bodies::{Asteroid, Planet},
-	constants::{DELTA, G},
+	bodies::Radius,
+	config::GameConfig,
+	constants::{ASTEROID_DESPAWN_DISTANCE, ASTEROID_RADIUS, DELTA, MAX_STEPS_PER_FRAME},
+	spatial::{rebuild_spatial_grid, AsteroidGravity, SpatialGrid},
+	state::AppState,
+	time_scale::TimeScale,
 };
 
 pub struct Velocity(pub Vec2);
 
 pub struct AngularVelocity(pub f32);
 
+/// How strongly a body pulls asteroids towards it, as a multiple of the `gravity` of the config,
+/// or pushes them away if negative.
+pub struct Mass(pub f32);
+
+/// Circular path of a moon around its planet.
+#[derive(Clone, Copy, Debug)]
+pub struct Orbit {
+	pub center: Entity,
+	pub distance: f32,
+	/// Radians from the x axis, counterclockwise.
+	pub angle: f32,
+	/// Radians per second.
+	pub angular_speed: f32,
+}
+
+impl Orbit {
+	/// Position of the moon relative to its planet.
+	pub fn offset(&self) -> Vec2 {
+		self.distance * Vec2::new(self.angle.cos(), self.angle.sin())
+	}
+
+	/// Lets `seconds` of simulation go by.
+	pub fn advance(&mut self, seconds: f32) {
+		self.angle = (self.angle + self.angular_speed * seconds) % TAU;
+	}
+}
+
+/// Number of fixed steps simulated so far, including the current one.
+#[derive(Default)]
+pub struct Tick(pub u64);
+
+/// Position and orientation of a body before the last fixed step, to draw it in between steps.
+pub struct PreviousTransform(pub Transform);
+
+/// Stage in which everything that must be reproducible runs, once per fixed step.
+pub const FIXED_UPDATE: &str = "fixed_update";
+
 pub struct PhysicsPlugin;
 
 impl Plugin for PhysicsPlugin {
 	fn build(&self, app: &mut AppBuilder) {
-		app.add_stage_after(
-			stage::UPDATE,
-			"fixed_update",
-			SystemStage::parallel()
-				.with_run_criteria(FixedTimestep::step(DELTA))
-				.with_system(gravity.system())
-				.with_system(velocity.system())
-				.with_system(angular_velocity.system()),
-		);
+		app.add_resource(Tick::default())
+			.add_resource(SimulationClock::default())
+			.add_resource(SpatialGrid::default())
+			.add_stage_after(
+				stage::UPDATE,
+				FIXED_UPDATE,
+				SystemStage::parallel()
+					.with_run_criteria(fixed_step.system())
+					.with_system(advance_tick.system())
+					.with_system(remember_previous_transforms.system())
+					.with_system(orbit.system())
+					.with_system(motion.system())
+					.with_system(angular_velocity.system())
+					// Once bodies moved, for every system that comes after
+					.with_system(rebuild_spatial_grid.system()),
+			);
+	}
+}
+
+/// Pace of the fixed update stage, which every fixed step system reads its step from.
+///
+/// This takes the place of Bevy's `FixedTimestep` and its `FixedTimesteps` resource, which can't
+/// run slower or faster than real time, be stepped by hand, or stop while the game isn't played.
+pub struct SimulationClock {
+	/// Simulated seconds per fixed step.
+	pub step: f64,
+	/// Simulated seconds per real second: below 1 for slow motion, above 1 to fast-forward.
+	pub speed: f64,
+	/// Whether the simulation only advances when steps are requested, for debugging.
+	pub stepping: bool,
+	/// Steps left to run while stepping.
+	pub requested_steps: u32,
+	/// Simulated time not yet run by the fixed update stage.
+	accumulator: f64,
+	/// Steps run during the current frame.
+	steps_this_frame: u32,
+	looping: bool,
+}
+
+impl Default for SimulationClock {
+	fn default() -> Self {
+		Self {
+			step: DELTA,
+			speed: 1.,
+			stepping: false,
+			requested_steps: 0,
+			accumulator: 0.,
+			steps_this_frame: 0,
+			looping: false,
+		}
+	}
+}
+
+impl SimulationClock {
+	/// Simulated seconds per fixed step.
+	pub fn delta(&self) -> f32 {
+		self.step as f32
+	}
+
+	/// How far the simulation is between the last step and the next one, from 0 to 1.
+	pub fn alpha(&self) -> f32 {
+		if self.stepping {
+			1.
+		} else {
+			(self.accumulator / self.step).min(1.) as f32
+		}
+	}
+}
+
+/// Runs the fixed update stage as many times as needed to keep up with real time, but only while
+/// the game is being played, so that no time piles up while it is paused.
+pub fn fixed_step(
+	time: Res<Time>,
+	state: Res<State<AppState>>,
+	time_scale: Res<TimeScale>,
+	mut clock: ResMut<SimulationClock>,
+) -> ShouldRun {
+	if *state.current() != AppState::Playing {
+		clock.looping = false;
+		return ShouldRun::No;
+	}
+
+	if clock.stepping {
+		clock.accumulator = 0.;
+		return if clock.requested_steps > 0 {
+			clock.requested_steps -= 1;
+			ShouldRun::Yes
+		} else {
+			ShouldRun::No
+		};
+	}
+
+	if !clock.looping {
+		clock.accumulator += clock.speed * time_scale.current as f64 * time.delta_seconds_f64();
+		clock.steps_this_frame = 0;
+	}
+	// Give up on catching up rather than taking longer and longer to simulate each frame
+	if clock.steps_this_frame >= MAX_STEPS_PER_FRAME {
+		clock.accumulator = clock.accumulator.min(clock.step);
+		clock.looping = false;
+		return ShouldRun::No;
+	}
+	if clock.accumulator >= clock.step {
+		clock.accumulator -= clock.step;
+		clock.steps_this_frame += 1;
+		clock.looping = true;
+		ShouldRun::YesAndLoop
+	} else {
+		clock.looping = false;
+		ShouldRun::No
+	}
+}
+
+pub fn advance_tick(mut tick: ResMut<Tick>) {
+	tick.0 += 1;
+}
+
+/// Position and velocity of a body moving under the gravity of planets.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyState {
+	pub position: Vec2,
+	pub velocity: Vec2,
+}
+
+/// A body pulling asteroids towards it, or pushing them away.
+#[derive(Clone, Copy, Debug)]
+pub struct Attractor {
+	pub position: Vec2,
+	pub mass: f32,
+	pub radius: f32,
+}
+
+/// Every body with a mass, as attractors.
+pub fn attractors<'a>(
+	bodies: impl IntoIterator<Item = (&'a Transform, &'a Mass, &'a Radius)>,
+) -> Vec<Attractor> {
+	bodies
+		.into_iter()
+		.map(|(transform, Mass(mass), Radius(radius))| Attractor {
+			position: transform.translation.truncate(),
+			mass: *mass,
+			radius: *radius,
+		})
+		.collect()
+}
+
+/// Attractors as predictions see them: moons move along their orbits, around planets that stay
+/// where they are.
+#[derive(Clone, Default)]
+pub struct MovingAttractors {
+	pub attractors: Vec<Attractor>,
+	/// Index in `attractors`, center and orbit of every moon.
+	orbits: Vec<(usize, Vec2, Orbit)>,
+}
+
+impl MovingAttractors {
+	/// Every body with a mass, with moons around wherever their planet is now.
+	pub fn new<'a>(
+		bodies: impl IntoIterator<Item = (&'a Transform, &'a Mass, &'a Radius, Option<&'a Orbit>)>,
+		centers: &Query<&Transform, Without<Orbit>>,
+	) -> Self {
+		let mut moving = Self::default();
+		for (transform, Mass(mass), Radius(radius), orbit) in bodies {
+			if let Some(orbit) = orbit {
+				if let Ok(center) = centers.get(orbit.center) {
+					let index = moving.attractors.len();
+					moving
+						.orbits
+						.push((index, center.translation.truncate(), *orbit));
+				}
+			}
+			moving.attractors.push(Attractor {
+				position: transform.translation.truncate(),
+				mass: *mass,
+				radius: *radius,
+			});
+		}
+		moving
+	}
+
+	/// Lets `dt` seconds of simulation go by, moving moons along their orbits.
+	pub fn advance(&mut self, dt: f32) {
+		for (index, center, orbit) in self.orbits.iter_mut() {
+			orbit.advance(dt);
+			self.attractors[*index].position = *center + orbit.offset();
+		}
+	}
+}
+
+/// Asteroids as attractors, with a mass growing with their area, grouped for them to pull each
+/// other, or nothing if they don't.
+pub fn asteroid_gravity(
+	config: &GameConfig,
+	asteroids: impl IntoIterator<Item = (Entity, Vec2, f32)>,
+) -> AsteroidGravity {
+	if config.asteroid_mass <= 0. {
+		return AsteroidGravity::default();
+	}
+	AsteroidGravity::new(asteroids.into_iter().map(|(entity, position, radius)| {
+		let attractor = Attractor {
+			position,
+			mass: config.asteroid_mass * (radius / ASTEROID_RADIUS).powi(2),
+			radius,
+		};
+		(entity, attractor)
+	}))
+}
+
+/// Acceleration due to the gravity of the attractors at `position`.
+///
+/// Gravity stops growing inside an attractor, so that overlapping bodies don't fling each other
+/// away.
+pub fn gravity_acceleration(gravity: f32, position: Vec2, attractors: &[Attractor]) -> Vec2 {
+	attractors
+		.iter()
+		.map(|attractor| {
+			let r = attractor.position - position;
+			let distance = r.length().max(attractor.radius);
+			r * gravity * attractor.mass / distance.powi(3)
+		})
+		.fold(Vec2::zero(), |total, acceleration| total + acceleration)
+}
+
+/// How bodies are advanced from one fixed step to the next.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Integrator {
+	/// Applies gravity to the velocity, then the new velocity to the position.  Cheap, and orbits
+	/// don't drift away, but they wobble.
+	SymplecticEuler,
+	/// Averages the acceleration at both ends of the step.  Orbits keep their energy.
+	VelocityVerlet,
+	/// Classic fourth order Runge-Kutta.  The most accurate over a single step, but orbits slowly
+	/// lose energy over very long runs.
+	RungeKutta4,
+}
+
+/// Advances a body by one fixed step.
+///
+/// Anything that needs to know where a body will go, like trajectory predictions, must use this,
+/// so that it agrees with what actually happens.
+pub fn step(
+	integrator: Integrator,
+	body: BodyState,
+	gravity: f32,
+	attractors: &[Attractor],
+	dt: f32,
+) -> BodyState {
+	let acceleration = |position| gravity_acceleration(gravity, position, attractors);
+	match integrator {
+		Integrator::SymplecticEuler => {
+			let velocity = body.velocity + dt * acceleration(body.position);
+			BodyState {
+				position: body.position + dt * velocity,
+				velocity,
+			}
+		}
+		Integrator::VelocityVerlet => {
+			let start = acceleration(body.position);
+			let position = body.position + dt * body.velocity + 0.5 * dt * dt * start;
+			let end = acceleration(position);
+			BodyState {
+				position,
+				velocity: body.velocity + 0.5 * dt * (start + end),
+			}
+		}
+		Integrator::RungeKutta4 => {
+			let (x, v) = (body.position, body.velocity);
+			let (k1x, k1v) = (v, acceleration(x));
+			let (k2x, k2v) = (v + 0.5 * dt * k1v, acceleration(x + 0.5 * dt * k1x));
+			let (k3x, k3v) = (v + 0.5 * dt * k2v, acceleration(x + 0.5 * dt * k2x));
+			let (k4x, k4v) = (v + dt * k3v, acceleration(x + dt * k3x));
+			BodyState {
+				position: x + dt / 6. * (k1x + 2. * k2x + 2. * k3x + k4x),
+				velocity: v + dt / 6. * (k1v + 2. * k2v + 2. * k3v + k4v),
+			}
+		}
+	}
+}
+
+/// Positions of an asteroid over the next `steps` fixed steps of `dt` seconds, stopping early when
+/// it crashes into an attractor or flies too far away to ever come back.
+///
+/// Moons move along their orbits first at each step, as they do in the fixed update stage.
+pub fn predict_trajectory(
+	integrator: Integrator,
+	mut body: BodyState,
+	radius: f32,
+	gravity: f32,
+	attractors: &MovingAttractors,
+	steps: usize,
+	dt: f32,
+) -> Vec<Vec2> {
+	let mut attractors = attractors.clone();
+	let mut trajectory = Vec::with_capacity(steps);
+	for _ in 0..steps {
+		attractors.advance(dt);
+		body = step(integrator, body, gravity, &attractors.attractors, dt);
+		trajectory.push(body.position);
+
+		let crashed = attractors.attractors.iter().any(|attractor| {
+			(attractor.position - body.position).length() <= radius + attractor.radius
+		});
+		if crashed || body.position.length() > ASTEROID_DESPAWN_DISTANCE {
+			break;
+		}
+	}
+	trajectory
+}
+
+/// Bodies that move on their own.
+type Moving = Or<(With<Velocity>, With<AngularVelocity>, With<Orbit>)>;
+
+pub fn remember_previous_transforms(
+	commands: &mut Commands,
+	mut bodies: Query<(Entity, &Transform, Option<&mut PreviousTransform>), Moving>,
+) {
+	for (entity, transform, previous) in bodies.iter_mut() {
+		match previous {
+			Some(mut previous) => previous.0 = *transform,
+			None => {
+				commands.insert_one(entity, PreviousTransform(*transform));
+			}
+		}
+	}
+}
+
+/// Moves moons along their orbits, around wherever their planet is.
+pub fn orbit(
+	clock: Res<SimulationClock>,
+	mut moons: Query<(&mut Orbit, &mut Transform)>,
+	centers: Query<&Transform, Without<Orbit>>,
+) {
+	for (mut orbit, mut transform) in moons.iter_mut() {
+		orbit.advance(clock.delta());
+		if let Ok(center) = centers.get(orbit.center) {
+			let position = center.translation.truncate() + orbit.offset();
+			transform.translation = position.extend(transform.translation.z);
+		}
 	}
 }
 
-// TODO use timesteps value instead of DELTA
-pub fn velocity(_timesteps: Res<FixedTimesteps>, mut query: Query<(&Velocity, &mut Transform)>) {
-	for (Velocity(vel), mut transform) in query.iter_mut() {
-		transform.translation += DELTA as f32 * vel.extend(0.);
+pub fn motion(
+	clock: Res<SimulationClock>,
+	config: Res<GameConfig>,
+	mut bodies: Query<(Entity, &mut Velocity, &mut Transform, &Radius)>,
+	attracting: Query<(&Transform, &Mass, &Radius), Without<Velocity>>,
+) {
+	let attractors = attractors(attracting.iter());
+	// Where asteroids were at the start of the step, so that they all move at once
+	let asteroids = asteroid_gravity(
+		&config,
+		bodies
+			.iter_mut()
+			.map(|(entity, _, transform, Radius(radius))| {
+				(entity, transform.translation.truncate(), *radius)
+			}),
+	);
+
+	let mut pulling = vec![];
+	for (entity, mut velocity, mut transform, _) in bodies.iter_mut() {
+		let position = transform.translation.truncate();
+		pulling.clear();
+		pulling.extend_from_slice(&attractors);
+		asteroids.pulling(entity, position, &mut pulling);
+
+		let body = step(
+			config.integrator,
+			BodyState {
+				position,
+				velocity: velocity.0,
+			},
+			config.gravity,
+			&pulling,
+			clock.delta(),
+		);
+		velocity.0 = body.velocity;
+		transform.translation = body.position.extend(transform.translation.z);
 	}
 }
 
 pub fn angular_velocity(
-	_timesteps: Res<FixedTimesteps>,
+	clock: Res<SimulationClock>,
 	mut query: Query<(&AngularVelocity, &mut Transform)>,
 ) {
 	for (AngularVelocity(av), mut transform) in query.iter_mut() {
-		transform.rotate(Quat::from_rotation_z(DELTA as f32 * av));
+		transform.rotate(Quat::from_rotation_z(clock.delta() * av));
 	}
 }
 
-pub fn gravity(
-	_timesteps: Res<FixedTimesteps>,
-	mut asteroids: Query<(&mut Velocity, &Transform), With<Asteroid>>,
-	planets: Query<&Transform, With<Planet>>,
-) {
-	for (
-		mut velocity,
-		Transform {
-			translation: asteroid,
-			..
-		},
-	) in asteroids.iter_mut()
-	{
-		for Transform {
-			translation: planet,
-			..
-		} in planets.iter()
-		{
-			let r = *planet - *asteroid;
-			let f = r * G * r.length_recip().powi(3);
-			velocity.0 += DELTA as f32 * f.truncate();
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const GRAVITY: f32 = 2e6;
+	/// Almost five revolutions of the orbit below.
+	const STEPS: usize = 10_000;
+	/// Largest change in the energy of a body, relative to where it started.
+	const ENERGY_TOLERANCE: f32 = 1e-3;
+
+	/// Runs a body on a circular orbit around a planet, and checks that it stays at the same
+	/// distance from it, within `radius_tolerance` of that distance, and keeps its energy.
+	fn assert_orbit_stays_circular(integrator: Integrator, radius_tolerance: f32) {
+		let planet = Attractor {
+			position: Vec2::zero(),
+			mass: 1.,
+			radius: 100.,
+		};
+		let radius = 400.;
+		let mut body = BodyState {
+			position: Vec2::new(radius, 0.),
+			velocity: Vec2::new(0., (GRAVITY / radius).sqrt()),
+		};
+		let energy = |body: BodyState| {
+			0.5 * body.velocity.length_squared()
+				- GRAVITY / (body.position - planet.position).length()
+		};
+		let initial_energy = energy(body);
+
+		for i in 0..STEPS {
+			body = step(integrator, body, GRAVITY, &[planet], DELTA as f32);
+
+			let radius_error = ((body.position - planet.position).length() - radius).abs() / radius;
+			assert!(
+				radius_error <= radius_tolerance,
+				"{:?} strayed {:.2}% from the orbit after {} steps",
+				integrator,
+				100. * radius_error,
+				i + 1
+			);
+			let energy_drift = ((energy(body) - initial_energy) / initial_energy).abs();
+			assert!(
+				energy_drift <= ENERGY_TOLERANCE,
+				"{:?} drifted {:.2}% in energy after {} steps",
+				integrator,
+				100. * energy_drift,
+				i + 1
+			);
 		}
 	}
+
+	#[test]
+	fn symplectic_euler_keeps_orbits_circular() {
+		// Wobbles, but doesn't drift away
+		assert_orbit_stays_circular(Integrator::SymplecticEuler, 5e-3);
+	}
+
+	#[test]
+	fn velocity_verlet_keeps_orbits_circular() {
+		assert_orbit_stays_circular(Integrator::VelocityVerlet, 1e-3);
+	}
+
+	#[test]
+	fn runge_kutta_keeps_orbits_circular() {
+		assert_orbit_stays_circular(Integrator::RungeKutta4, 1e-3);
+	}
 }
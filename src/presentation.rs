@@ -0,0 +1,329 @@
+use bevy::{prelude::*, render::pass::ClearColor};
+
+use crate::{
+	accessibility::{AccessibilityPlugin, ColorblindMode},
+	atlas::{Animation, AtlasPlugin, SpriteAtlas},
+	audio::AudioPlugin,
+	bodies::{Asteroid, GravityWell, Impact, Moon},
+	constants::{
+		ASTEROID_SPRITES, EXPLOSION_ANIMATION, MAX_SIMULATION_SPEED, MENU_WIDTH,
+		MIN_SIMULATION_SPEED, PLANET_SPRITE, SCREEN_HEIGHT, SCREEN_WIDTH, VIEWPORT_SCALE,
+	},
+	evolution::EvolutionPlugin,
+	gui::GuiPlugin,
+	loading::{GameAssets, LoadingPlugin},
+	physics::{Mass, PreviousTransform, SimulationClock},
+	save::{save_on_demand, save_on_quit},
+	screen_shaker::{ScreenShakeBundle, ScreenShakePlugin},
+	slingshot::{SlingshotControlsPlugin, WorldCamera},
+	state::AppState,
+	time_scale::{fast_forward_controls, TimeScale},
+	trajectory::TrajectoryPlugin,
+};
+
+/// Tint of the planet sprite drawn for moons.
+const MOON_COLOR: Color = Color::rgb_linear(0.6, 0.6, 0.65);
+
+/// Tints of the planet sprite drawn for gravity wells pulling asteroids in, and for the ones
+/// pushing them away.
+const GRAVITY_WELL_COLOR: Color = Color::rgb_linear(0.15, 0.05, 0.25);
+const REPELLER_COLOR: Color = Color::rgb_linear(1.0, 0.6, 0.3);
+
+/// Stage in which bodies are moved to where they are drawn, in between two fixed steps.
+pub const INTERPOLATION: &str = "interpolation";
+
+/// Everything the player sees and touches: sprites, camera, GUI and slingshot.
+///
+/// Sprites are attached to the bodies spawned by the simulation as soon as they appear.
+///
+/// Needs the recipes and levels, whose sprites are checked against the atlas.
+pub struct PresentationPlugin {
+	pub colorblind_mode: ColorblindMode,
+}
+
+impl Plugin for PresentationPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		// Players start from the main menu, once everything is loaded, rather than right in the
+		// game
+		app.add_resource(State::new(AppState::Loading))
+			.add_plugin(LoadingPlugin)
+			.add_startup_system(setup.system())
+			.add_plugin(AtlasPlugin)
+			// Sounds
+			.add_plugin(AudioPlugin)
+			// GUI
+			.add_plugin(GuiPlugin)
+			// Sprites
+			.add_plugin(EvolutionPlugin)
+			.add_system(attach_moon_sprites.system())
+			.add_system(attach_gravity_well_sprites.system())
+			.add_system(attach_asteroid_sprites.system())
+			.add_system(spawn_explosions.system())
+			.add_system(animate_explosion.system())
+			// Slingshot
+			.add_plugin(SlingshotControlsPlugin)
+			.add_plugin(TrajectoryPlugin)
+			// Screen shake
+			.add_plugin(ScreenShakePlugin)
+			// Saving
+			.add_system(save_on_demand.system())
+			.add_system(save_on_quit.system())
+			// Pace of the simulation
+			.add_system(fast_forward_controls.system())
+			.add_system(simulation_rate_controls.system())
+			// After transforms are propagated, right before rendering, so that the simulation keeps
+			// its own transforms
+			.add_stage_after(
+				stage::POST_UPDATE,
+				INTERPOLATION,
+				SystemStage::parallel().with_system(interpolate_transforms.system()),
+			)
+			// Accessibility, once there is a stage to draw badges in
+			.add_plugin(AccessibilityPlugin {
+				colorblind_mode: self.colorblind_mode,
+			});
+	}
+}
+
+/// Plays the explosion animation, then goes away.
+pub struct Explosion {
+	/// Time left on the current frame.
+	pub timer: Timer,
+	pub frame: usize,
+}
+
+impl Explosion {
+	pub fn new(animation: &Animation) -> Self {
+		Explosion {
+			timer: Timer::from_seconds(animation.frames[0].duration, false),
+			frame: 0,
+		}
+	}
+}
+
+pub fn spawn_explosion(
+	commands: &mut Commands,
+	game_assets: &GameAssets,
+	atlas: &SpriteAtlas,
+	transform: Transform,
+) {
+	let animation = atlas.animation(EXPLOSION_ANIMATION);
+	commands
+		.spawn(sprite_sheet(
+			game_assets,
+			animation.frames[0].sprite,
+			transform,
+		))
+		.with(Explosion::new(animation));
+}
+
+fn setup(commands: &mut Commands) {
+	commands
+		.insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
+		.insert_resource(WindowDescriptor {
+			title: "Biomass".to_string(),
+			width: SCREEN_WIDTH,
+			height: SCREEN_HEIGHT,
+			resizable: false,
+			vsync: true,
+			..Default::default()
+		})
+		// Screen shaker
+		.spawn(ScreenShakeBundle::default())
+		.with_children(|parent| {
+			// Camera
+			parent
+				.spawn(Camera2dBundle {
+					transform: Transform::from_translation(Vec3::new(-MENU_WIDTH, 0., 0.))
+						* Transform::from_scale(Vec3::splat(1. / VIEWPORT_SCALE)),
+					..Default::default()
+				})
+				.with(WorldCamera);
+		});
+}
+
+pub fn sprite_sheet(
+	game_assets: &GameAssets,
+	index: u32,
+	transform: Transform,
+) -> SpriteSheetBundle {
+	SpriteSheetBundle {
+		texture_atlas: game_assets.sprites.clone(),
+		sprite: TextureAtlasSprite::new(index),
+		transform,
+		..Default::default()
+	}
+}
+
+pub fn attach_moon_sprites(
+	commands: &mut Commands,
+	game_assets: Res<GameAssets>,
+	atlas: Res<SpriteAtlas>,
+	moons: Query<(Entity, &Transform), Added<Moon>>,
+) {
+	for (entity, transform) in moons.iter() {
+		let mut sprite_sheet = sprite_sheet(&game_assets, atlas.sprite(PLANET_SPRITE), *transform);
+		sprite_sheet.sprite.color = MOON_COLOR;
+		commands.insert(entity, sprite_sheet);
+	}
+}
+
+pub fn attach_gravity_well_sprites(
+	commands: &mut Commands,
+	game_assets: Res<GameAssets>,
+	atlas: Res<SpriteAtlas>,
+	wells: Query<(Entity, &Transform, &Mass), Added<GravityWell>>,
+) {
+	for (entity, transform, Mass(mass)) in wells.iter() {
+		let mut sprite_sheet = sprite_sheet(&game_assets, atlas.sprite(PLANET_SPRITE), *transform);
+		sprite_sheet.sprite.color = if *mass >= 0. {
+			GRAVITY_WELL_COLOR
+		} else {
+			REPELLER_COLOR
+		};
+		commands.insert(entity, sprite_sheet);
+	}
+}
+
+pub fn attach_asteroid_sprites(
+	commands: &mut Commands,
+	game_assets: Res<GameAssets>,
+	atlas: Res<SpriteAtlas>,
+	asteroids: Query<(Entity, &Transform, &Asteroid), Added<Asteroid>>,
+) {
+	for (entity, transform, Asteroid(asteroid)) in asteroids.iter() {
+		let index = atlas.sprite(ASTEROID_SPRITES[*asteroid]);
+		commands.insert(entity, sprite_sheet(&game_assets, index, *transform));
+	}
+}
+
+pub fn spawn_explosions(
+	commands: &mut Commands,
+	game_assets: Res<GameAssets>,
+	atlas: Res<SpriteAtlas>,
+	mut impact_reader: Local<EventReader<Impact>>,
+	impacts: Res<Events<Impact>>,
+) {
+	for impact in impact_reader.iter(&impacts) {
+		let transform = Transform::from_translation(impact.position);
+		spawn_explosion(commands, &game_assets, &atlas, transform);
+	}
+}
+
+pub fn animate_explosion(
+	time: Res<Time>,
+	time_scale: Res<TimeScale>,
+	atlas: Res<SpriteAtlas>,
+	commands: &mut Commands,
+	mut query: Query<(Entity, &mut Explosion, &mut TextureAtlasSprite)>,
+) {
+	let frames = &atlas.animation(EXPLOSION_ANIMATION).frames;
+	for (entity, mut explosion, mut sprite) in query.iter_mut() {
+		if explosion
+			.timer
+			.tick(time_scale.scale(time.delta_seconds()))
+			.just_finished()
+		{
+			explosion.frame += 1;
+			match frames.get(explosion.frame) {
+				Some(frame) => {
+					sprite.index = frame.sprite;
+					explosion.timer = Timer::from_seconds(frame.duration, false);
+				}
+				None => {
+					commands.despawn(entity);
+				}
+			}
+		}
+	}
+}
+
+/// Draws bodies in between their last two fixed steps, so that they move smoothly at any pace of
+/// the simulation.
+///
+/// Transforms were already propagated from where bodies are, so their children are moved along
+/// with them again.
+pub fn interpolate_transforms(
+	clock: Res<SimulationClock>,
+	mut bodies: Query<(
+		&Transform,
+		&PreviousTransform,
+		&mut GlobalTransform,
+		Option<&Children>,
+	)>,
+	mut descendants: Query<
+		(&Transform, &mut GlobalTransform, Option<&Children>),
+		Without<PreviousTransform>,
+	>,
+) {
+	let alpha = clock.alpha();
+	for (transform, PreviousTransform(previous), mut global_transform, children) in
+		bodies.iter_mut()
+	{
+		global_transform.translation = previous.translation.lerp(transform.translation, alpha);
+		global_transform.rotation = previous.rotation.lerp(transform.rotation, alpha);
+		if let Some(children) = children {
+			propagate_interpolation(&global_transform, children, &mut descendants);
+		}
+	}
+}
+
+fn propagate_interpolation(
+	parent: &GlobalTransform,
+	children: &[Entity],
+	descendants: &mut Query<
+		(&Transform, &mut GlobalTransform, Option<&Children>),
+		Without<PreviousTransform>,
+	>,
+) {
+	for child in children.iter() {
+		let (global_transform, grandchildren) = match descendants.get_mut(*child) {
+			Ok((transform, mut global_transform, grandchildren)) => {
+				*global_transform = parent.mul_transform(*transform);
+				let grandchildren: Vec<Entity> = grandchildren
+					.map(|grandchildren| grandchildren.iter().copied().collect())
+					.unwrap_or_default();
+				(*global_transform, grandchildren)
+			}
+			Err(_) => continue,
+		};
+		propagate_interpolation(&global_transform, &grandchildren, descendants);
+	}
+}
+
+/// Debug controls of the pace of the simulation: `-` and `=` slow it down and speed it up, `0`
+/// brings it back to normal, `F10` toggles stepping and `.` runs a single step while stepping.
+pub fn simulation_rate_controls(
+	keyboard_input: Res<Input<KeyCode>>,
+	mut clock: ResMut<SimulationClock>,
+) {
+	let speed = clock.speed;
+	if keyboard_input.just_pressed(KeyCode::Minus) {
+		clock.speed = (speed / 2.).max(MIN_SIMULATION_SPEED);
+	}
+	if keyboard_input.just_pressed(KeyCode::Equals) {
+		clock.speed = (speed * 2.).min(MAX_SIMULATION_SPEED);
+	}
+	if keyboard_input.just_pressed(KeyCode::Key0) {
+		clock.speed = 1.;
+	}
+	if clock.speed != speed {
+		info!("simulation speed set to {}x", clock.speed);
+	}
+
+	if keyboard_input.just_pressed(KeyCode::F10) {
+		clock.stepping = !clock.stepping;
+		clock.requested_steps = 0;
+		info!(
+			"simulation {}",
+			if clock.stepping {
+				"paused, press `.` to step"
+			} else {
+				"resumed"
+			}
+		);
+	}
+	if clock.stepping && keyboard_input.just_pressed(KeyCode::Period) {
+		clock.requested_steps += 1;
+	}
+}
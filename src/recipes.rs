@@ -0,0 +1,340 @@
+use std::{fmt, fs, io, path::PathBuf};
+
+use serde::{
+	de::{MapAccess, Visitor},
+	Deserialize, Deserializer,
+};
+
+use crate::constants::{ASTEROID_NAMES, N_ASTEROIDS};
+
+pub type Ingredients = [usize; N_ASTEROIDS];
+
+pub fn add_ingredient(ingredients: &Ingredients, index: usize) -> Ingredients {
+	let mut new = *ingredients;
+	new[index] += 1;
+	new
+}
+
+pub struct Recipe {
+	pub name: String,
+	pub requirement: u32,
+	pub reward: u32,
+	pub ingredients: Ingredients,
+	pub appearance: Appearance,
+}
+
+/// Red, green, blue and alpha, from 0 to 1.
+pub type Tint = (f32, f32, f32, f32);
+
+fn opaque_white() -> Tint {
+	(1., 1., 1., 1.)
+}
+
+fn one() -> f32 {
+	1.
+}
+
+/// How a planet looks at a stage of evolution.
+#[derive(Clone, Deserialize)]
+pub struct Appearance {
+	/// Name of the sprite in the atlas, the one the level gives the planet by default.
+	#[serde(default)]
+	pub sprite: Option<String>,
+	#[serde(default = "opaque_white")]
+	pub tint: Tint,
+	/// Drawn over the planet, in order.
+	#[serde(default)]
+	pub overlays: Vec<Overlay>,
+}
+
+impl Default for Appearance {
+	fn default() -> Self {
+		Self {
+			sprite: None,
+			tint: opaque_white(),
+			overlays: vec![],
+		}
+	}
+}
+
+/// A layer drawn over a planet, such as an atmosphere.
+#[derive(Clone, Deserialize)]
+pub struct Overlay {
+	pub sprite: String,
+	#[serde(default = "opaque_white")]
+	pub tint: Tint,
+	/// Relative to the planet.
+	#[serde(default = "one")]
+	pub scale: f32,
+	/// Radians per second, relative to the planet.
+	#[serde(default)]
+	pub angular_velocity: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IngredientsComparison {
+	TooFew,
+	TooMany,
+	JustRight,
+}
+
+impl Recipe {
+	pub fn check_ingredients(&self, ingredients: &Ingredients) -> IngredientsComparison {
+		let mut result = IngredientsComparison::JustRight;
+		for (i, j) in self.ingredients.iter().zip(ingredients) {
+			if j < i {
+				result = IngredientsComparison::TooFew;
+			} else if j > i {
+				return IngredientsComparison::TooMany;
+			}
+		}
+		result
+	}
+}
+
+/// The evolution ladder of the planet, sorted by increasing biomass requirement.
+pub struct Recipes(pub Vec<Recipe>);
+
+/// A recipe as it is written in the recipes file, with ingredients keyed by asteroid name.
+#[derive(Deserialize)]
+struct RecipeDescription {
+	name: String,
+	requirement: u32,
+	reward: u32,
+	#[serde(deserialize_with = "ingredient_entries")]
+	ingredients: Vec<(String, usize)>,
+	#[serde(default)]
+	appearance: Appearance,
+}
+
+/// Reads the ingredients map entry by entry, so that an asteroid listed twice can be reported
+/// instead of silently overwriting the first count.
+fn ingredient_entries<'de, D: Deserializer<'de>>(
+	deserializer: D,
+) -> Result<Vec<(String, usize)>, D::Error> {
+	struct EntriesVisitor;
+
+	impl<'de> Visitor<'de> for EntriesVisitor {
+		type Value = Vec<(String, usize)>;
+
+		fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			write!(f, "a map of asteroid names to counts")
+		}
+
+		fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+			let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+			while let Some(entry) = map.next_entry()? {
+				entries.push(entry);
+			}
+			Ok(entries)
+		}
+	}
+
+	deserializer.deserialize_map(EntriesVisitor)
+}
+
+#[derive(Debug)]
+pub enum RecipesError {
+	Io(io::Error),
+	Parse(ron::Error),
+	Empty,
+	FirstRequirementNotZero(String),
+	RequirementNotIncreasing(String),
+	NoIngredients(String),
+	UnknownAsteroid(String, String),
+	DuplicateAsteroid(String, String),
+}
+
+impl fmt::Display for RecipesError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RecipesError::Io(err) => write!(f, "could not read file: {}", err),
+			RecipesError::Parse(err) => write!(f, "could not parse file: {}", err),
+			RecipesError::Empty => write!(f, "there must be at least one recipe"),
+			RecipesError::FirstRequirementNotZero(name) => write!(
+				f,
+				"the first recipe, `{}`, must have a requirement of 0",
+				name
+			),
+			RecipesError::RequirementNotIncreasing(name) => write!(
+				f,
+				"recipe `{}` must require more biomass than the one before it",
+				name
+			),
+			RecipesError::NoIngredients(name) => {
+				write!(f, "recipe `{}` must have at least one ingredient", name)
+			}
+			RecipesError::UnknownAsteroid(name, asteroid) => write!(
+				f,
+				"recipe `{}` uses unknown asteroid `{}` (expected one of: {})",
+				name,
+				asteroid,
+				ASTEROID_NAMES.join(", ")
+			),
+			RecipesError::DuplicateAsteroid(name, asteroid) => write!(
+				f,
+				"recipe `{}` lists asteroid `{}` more than once",
+				name, asteroid
+			),
+		}
+	}
+}
+
+impl Recipes {
+	pub fn load(path: impl Into<PathBuf>) -> Result<Self, RecipesError> {
+		let contents = fs::read_to_string(path.into()).map_err(RecipesError::Io)?;
+		Self::from_ron(&contents)
+	}
+
+	pub fn from_ron(contents: &str) -> Result<Self, RecipesError> {
+		let descriptions: Vec<RecipeDescription> =
+			ron::de::from_str(contents).map_err(RecipesError::Parse)?;
+
+		let mut recipes: Vec<Recipe> = Vec::with_capacity(descriptions.len());
+		for description in descriptions {
+			match recipes.last() {
+				None if description.requirement != 0 => {
+					return Err(RecipesError::FirstRequirementNotZero(description.name));
+				}
+				Some(previous) if description.requirement <= previous.requirement => {
+					return Err(RecipesError::RequirementNotIncreasing(description.name));
+				}
+				_ => {}
+			}
+
+			let mut ingredients = [0; N_ASTEROIDS];
+			let mut listed = [false; N_ASTEROIDS];
+			for (asteroid, count) in description.ingredients {
+				match ASTEROID_NAMES.iter().position(|name| *name == asteroid) {
+					Some(index) if listed[index] => {
+						return Err(RecipesError::DuplicateAsteroid(description.name, asteroid));
+					}
+					Some(index) => {
+						ingredients[index] = count;
+						listed[index] = true;
+					}
+					None => return Err(RecipesError::UnknownAsteroid(description.name, asteroid)),
+				}
+			}
+			if ingredients.iter().all(|count| *count == 0) {
+				return Err(RecipesError::NoIngredients(description.name));
+			}
+
+			recipes.push(Recipe {
+				name: description.name,
+				requirement: description.requirement,
+				reward: description.reward,
+				ingredients,
+				appearance: description.appearance,
+			});
+		}
+
+		if recipes.is_empty() {
+			return Err(RecipesError::Empty);
+		}
+		Ok(Self(recipes))
+	}
+
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	/// Never true, since there must be at least one recipe when loading them.
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+}
+
+impl std::ops::Index<usize> for Recipes {
+	type Output = Recipe;
+
+	fn index(&self, index: usize) -> &Recipe {
+		&self.0[index]
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn error_of(contents: &str) -> RecipesError {
+		match Recipes::from_ron(contents) {
+			Ok(_) => panic!("expected the recipes to be rejected"),
+			Err(err) => err,
+		}
+	}
+
+	#[test]
+	fn loads_the_shipped_recipes() {
+		let recipes = Recipes::load(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/recipes.ron"))
+			.unwrap_or_else(|err| panic!("invalid shipped recipes: {}", err));
+		assert!(!recipes.is_empty());
+		assert_eq!(recipes[0].requirement, 0);
+	}
+
+	#[test]
+	fn reads_ingredients_by_asteroid_name() {
+		let recipes = Recipes::from_ron(
+			r#"[
+				(name: "First", requirement: 0, reward: 10, ingredients: {"teal": 1}),
+				(name: "Second", requirement: 40, reward: 20, ingredients: {"blue": 2, "grey": 1}),
+			]"#,
+		)
+		.unwrap();
+		assert_eq!(recipes.len(), 2);
+		assert_eq!(recipes[0].ingredients, [1, 0, 0, 0, 0, 0, 0, 0, 0]);
+		assert_eq!(recipes[1].ingredients, [0, 2, 0, 0, 0, 0, 0, 0, 1]);
+	}
+
+	#[test]
+	fn rejects_no_recipes() {
+		assert!(matches!(error_of("[]"), RecipesError::Empty));
+	}
+
+	#[test]
+	fn rejects_a_first_requirement_other_than_zero() {
+		let err =
+			error_of(r#"[(name: "First", requirement: 5, reward: 10, ingredients: {"teal": 1})]"#);
+		assert!(matches!(err, RecipesError::FirstRequirementNotZero(name) if name == "First"));
+	}
+
+	#[test]
+	fn rejects_requirements_that_do_not_increase() {
+		let err = error_of(
+			r#"[
+				(name: "First", requirement: 0, reward: 10, ingredients: {"teal": 1}),
+				(name: "Second", requirement: 40, reward: 20, ingredients: {"blue": 1}),
+				(name: "Third", requirement: 40, reward: 20, ingredients: {"olive": 1}),
+			]"#,
+		);
+		assert!(matches!(err, RecipesError::RequirementNotIncreasing(name) if name == "Third"));
+	}
+
+	#[test]
+	fn rejects_a_recipe_without_ingredients() {
+		let err =
+			error_of(r#"[(name: "First", requirement: 0, reward: 10, ingredients: {"teal": 0})]"#);
+		assert!(matches!(err, RecipesError::NoIngredients(name) if name == "First"));
+	}
+
+	#[test]
+	fn rejects_an_unknown_asteroid() {
+		let err =
+			error_of(r#"[(name: "First", requirement: 0, reward: 10, ingredients: {"pink": 1})]"#);
+		assert!(matches!(
+			err,
+			RecipesError::UnknownAsteroid(name, asteroid) if name == "First" && asteroid == "pink"
+		));
+	}
+
+	#[test]
+	fn rejects_an_asteroid_listed_twice() {
+		let err = error_of(
+			r#"[(name: "First", requirement: 0, reward: 10, ingredients: {"teal": 1, "teal": 2})]"#,
+		);
+		assert!(matches!(
+			err,
+			RecipesError::DuplicateAsteroid(name, asteroid) if name == "First" && asteroid == "teal"
+		));
+	}
+}
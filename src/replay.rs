@@ -0,0 +1,191 @@
+use std::{
+	collections::VecDeque,
+	fmt,
+	fs::{self, File},
+	io::{self, Write},
+	path::Path,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	config::{ConfigError, GameConfig},
+	slingshot::{SlingshotAction, SlingshotInput},
+};
+
+/// Version of the replay format, bumped whenever recorded replays stop playing back the same.
+pub const REPLAY_VERSION: u32 = 1;
+
+/// First line of a replay file.
+///
+/// The level and the config are recorded along with the seed, since a replay only plays back the
+/// same in the level and with the tuning it was recorded with.
+#[derive(Serialize, Deserialize)]
+struct ReplayHeader {
+	version: u32,
+	seed: u64,
+	level: usize,
+	config: GameConfig,
+}
+
+/// Every following line of a replay file: a slingshot input and the fixed step it was applied
+/// on.
+#[derive(Serialize, Deserialize)]
+struct RecordedInput {
+	tick: u64,
+	pointer: u64,
+	action: SlingshotAction,
+	x: f32,
+	y: f32,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+	Io(io::Error),
+	Parse(usize, ron::Error),
+	MissingHeader,
+	UnsupportedVersion(u32),
+	InvalidConfig(ConfigError),
+}
+
+impl fmt::Display for ReplayError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ReplayError::Io(err) => write!(f, "could not read file: {}", err),
+			ReplayError::Parse(line, err) => write!(f, "could not parse line {}: {}", line, err),
+			ReplayError::MissingHeader => write!(f, "the file is empty"),
+			ReplayError::UnsupportedVersion(version) => write!(
+				f,
+				"the file was recorded with version {} of the format, but only version {} is \
+				 supported",
+				version, REPLAY_VERSION
+			),
+			ReplayError::InvalidConfig(err) => write!(f, "the recorded config is invalid: {}", err),
+		}
+	}
+}
+
+/// Inputs of a recorded run, fed to the slingshot instead of the player's.
+///
+/// Once every input has been played back, the player takes over.
+pub struct Replay {
+	pub seed: u64,
+	pub level: usize,
+	/// Config the inputs were recorded with, or `None` for an empty replay.
+	pub config: Option<GameConfig>,
+	inputs: VecDeque<(u64, SlingshotInput)>,
+}
+
+impl Replay {
+	/// A replay with no inputs, so that the player is in control from the start.
+	pub fn empty() -> Self {
+		Self {
+			seed: 0,
+			level: 0,
+			config: None,
+			inputs: VecDeque::new(),
+		}
+	}
+
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+		let contents = fs::read_to_string(path).map_err(ReplayError::Io)?;
+		let mut lines = contents
+			.lines()
+			.enumerate()
+			.filter(|(_, line)| !line.trim().is_empty());
+
+		let (index, header) = lines.next().ok_or(ReplayError::MissingHeader)?;
+		let header: ReplayHeader =
+			ron::de::from_str(header).map_err(|err| ReplayError::Parse(index + 1, err))?;
+		if header.version != REPLAY_VERSION {
+			return Err(ReplayError::UnsupportedVersion(header.version));
+		}
+		header
+			.config
+			.validate()
+			.map_err(ReplayError::InvalidConfig)?;
+
+		let mut inputs = VecDeque::new();
+		for (index, line) in lines {
+			let input: RecordedInput =
+				ron::de::from_str(line).map_err(|err| ReplayError::Parse(index + 1, err))?;
+			inputs.push_back((
+				input.tick,
+				SlingshotInput {
+					pointer: input.pointer,
+					action: input.action,
+					position: Vec2::new(input.x, input.y),
+				},
+			));
+		}
+
+		Ok(Self {
+			seed: header.seed,
+			level: header.level,
+			config: Some(header.config),
+			inputs,
+		})
+	}
+
+	pub fn is_playing(&self) -> bool {
+		!self.inputs.is_empty()
+	}
+
+	/// Removes and returns the inputs recorded on the given tick.
+	pub fn take_inputs(&mut self, tick: u64) -> Vec<SlingshotInput> {
+		let mut inputs = vec![];
+		while let Some((input_tick, input)) = self.inputs.front() {
+			if *input_tick > tick {
+				break;
+			}
+			inputs.push(*input);
+			self.inputs.pop_front();
+		}
+		inputs
+	}
+}
+
+/// Writes every slingshot input to a replay file as soon as it is applied, so that nothing is
+/// lost if the game is killed.
+pub struct Recorder(Option<File>);
+
+impl Recorder {
+	pub fn disabled() -> Self {
+		Self(None)
+	}
+
+	pub fn create(
+		path: impl AsRef<Path>,
+		seed: u64,
+		level: usize,
+		config: &GameConfig,
+	) -> io::Result<Self> {
+		let mut file = File::create(path)?;
+		let header = ReplayHeader {
+			version: REPLAY_VERSION,
+			seed,
+			level,
+			config: config.clone(),
+		};
+		writeln!(file, "{}", ron::ser::to_string(&header).unwrap())?;
+		Ok(Self(Some(file)))
+	}
+
+	pub fn record(&mut self, tick: u64, input: &SlingshotInput) {
+		if let Some(file) = &mut self.0 {
+			let input = RecordedInput {
+				tick,
+				pointer: input.pointer,
+				action: input.action,
+				x: input.position.x,
+				y: input.position.y,
+			};
+			let line = ron::ser::to_string(&input).unwrap();
+			if let Err(err) = writeln!(file, "{}", line) {
+				warn!("could not record input, recording stopped: {}", err);
+				self.0 = None;
+			}
+		}
+	}
+}
@@ -0,0 +1,61 @@
+use rand::{Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+/// Random number generator shared by every random decision of the simulation.
+///
+/// It is seeded once at startup, so that a run can be replayed exactly from its seed.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameRng {
+	seed: u64,
+	rng: ChaCha8Rng,
+}
+
+impl GameRng {
+	pub fn from_seed(seed: u64) -> Self {
+		Self {
+			seed,
+			rng: ChaCha8Rng::seed_from_u64(seed),
+		}
+	}
+
+	pub fn seed(&self) -> u64 {
+		self.seed
+	}
+}
+
+impl RngCore for GameRng {
+	fn next_u32(&mut self) -> u32 {
+		self.rng.next_u32()
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		self.rng.next_u64()
+	}
+
+	fn fill_bytes(&mut self, dest: &mut [u8]) {
+		self.rng.fill_bytes(dest)
+	}
+
+	fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+		self.rng.try_fill_bytes(dest)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::Rng;
+
+	use super::*;
+
+	fn draws(seed: u64) -> Vec<u32> {
+		let mut rng = GameRng::from_seed(seed);
+		(0..100).map(|_| rng.gen()).collect()
+	}
+
+	#[test]
+	fn same_seed_draws_the_same() {
+		assert_eq!(draws(42), draws(42));
+		assert_ne!(draws(1), draws(2));
+	}
+}
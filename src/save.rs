@@ -0,0 +1,475 @@
+use std::{
+	fmt, fs, io,
+	path::{Path, PathBuf},
+};
+
+use bevy::{
+	app::{startup_stage, AppExit},
+	prelude::*,
+	window::WindowCloseRequested,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	bodies::{
+		spawn_asteroid, spawn_gravity_wells, spawn_moon, spawn_planet, Asteroid, GravityWell, Moon,
+		Planet, PlanetSprite, Radius,
+	},
+	level::{LevelTime, Levels},
+	physics::{AngularVelocity, Mass, Orbit, Tick, Velocity},
+	recipes::Ingredients,
+	rng::GameRng,
+	spawning::{SpawnDirector, SpawnScheduler},
+	Biomass, CurrentIngredients,
+};
+
+/// Version of the save format, bumped whenever older saves can't be loaded anymore.
+pub const SAVE_VERSION: u32 = 1;
+
+/// Where the game is saved to and resumed from, if anywhere.
+pub struct SaveFile(pub Option<PathBuf>);
+
+/// Sent to save the game at the end of the frame.
+pub struct SaveGame;
+
+#[derive(Serialize, Deserialize)]
+struct SavedBody {
+	translation: [f32; 3],
+	rotation: [f32; 4],
+	velocity: [f32; 2],
+	angular_velocity: f32,
+	radius: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedMoon {
+	radius: f32,
+	mass: f32,
+	distance: f32,
+	angle: f32,
+	angular_speed: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPlanet {
+	body: SavedBody,
+	mass: f32,
+	sprite: String,
+	biomass: u32,
+	current_ingredients: Ingredients,
+	moons: Vec<SavedMoon>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedAsteroid {
+	kind: usize,
+	body: SavedBody,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedGame {
+	version: u32,
+	/// Index of the level, whose gravity wells don't need saving.
+	level: usize,
+	level_time: f32,
+	tick: u64,
+	spawn_scheduler: SpawnScheduler,
+	spawn_director: SpawnDirector,
+	rng: GameRng,
+	planets: Vec<SavedPlanet>,
+	asteroids: Vec<SavedAsteroid>,
+}
+
+/// Just the version of a save, to tell apart older saves from corrupted ones.
+#[derive(Deserialize)]
+struct SavedVersion {
+	version: u32,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+	Io(io::Error),
+	Parse(ron::Error),
+	UnsupportedVersion(u32),
+	UnknownLevel(usize),
+}
+
+impl fmt::Display for SaveError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SaveError::Io(err) => write!(f, "could not access file: {}", err),
+			SaveError::Parse(err) => write!(f, "the save is corrupted: {}", err),
+			SaveError::UnsupportedVersion(version) => write!(
+				f,
+				"the save is from version {} of the format, but only version {} is supported",
+				version, SAVE_VERSION
+			),
+			SaveError::UnknownLevel(level) => {
+				write!(
+					f,
+					"the save is from level {}, which doesn't exist",
+					level + 1
+				)
+			}
+		}
+	}
+}
+
+impl SavedGame {
+	fn load(path: &Path) -> Result<Self, SaveError> {
+		let contents = fs::read_to_string(path).map_err(SaveError::Io)?;
+		let SavedVersion { version } = ron::de::from_str(&contents).map_err(SaveError::Parse)?;
+		if version != SAVE_VERSION {
+			return Err(SaveError::UnsupportedVersion(version));
+		}
+		ron::de::from_str(&contents).map_err(SaveError::Parse)
+	}
+
+	/// Writes the save next to its destination first, so that a crash can't leave a half-written
+	/// save behind.
+	fn write(&self, path: &Path) -> Result<(), SaveError> {
+		let contents =
+			ron::ser::to_string_pretty(self, Default::default()).map_err(SaveError::Parse)?;
+		let temporary = path.with_extension("tmp");
+		fs::write(&temporary, contents).map_err(SaveError::Io)?;
+		fs::rename(&temporary, path).map_err(SaveError::Io)
+	}
+}
+
+impl SavedBody {
+	fn new(transform: &Transform, velocity: Vec2, angular_velocity: f32, radius: f32) -> Self {
+		Self {
+			translation: transform.translation.into(),
+			rotation: transform.rotation.into(),
+			velocity: velocity.into(),
+			angular_velocity,
+			radius,
+		}
+	}
+
+	fn transform(&self) -> Transform {
+		let mut transform = Transform::from_translation(self.translation.into());
+		transform.rotation = self.rotation.into();
+		transform
+	}
+}
+
+/// Loads the save at `path`, if there is one, from one of the first `levels`.  A save that can't
+/// be loaded is moved to a `.bak` file, so that it isn't overwritten by the new game.
+fn resume(path: &Path, levels: usize) -> Option<SavedGame> {
+	if !path.exists() {
+		return None;
+	}
+	let save = SavedGame::load(path).and_then(|save| {
+		if save.level < levels {
+			Ok(save)
+		} else {
+			Err(SaveError::UnknownLevel(save.level))
+		}
+	});
+	match save {
+		Ok(save) => Some(save),
+		Err(err) => {
+			let backup = path.with_extension("bak");
+			warn!(
+				"could not resume game from `{}`, starting a new one ({}); it was moved to `{}`",
+				path.display(),
+				err,
+				backup.display()
+			);
+			if let Err(err) = fs::rename(path, &backup) {
+				warn!("could not move `{}`: {}", path.display(), err);
+			}
+			None
+		}
+	}
+}
+
+pub struct SavePlugin {
+	pub file: Option<PathBuf>,
+}
+
+impl Plugin for SavePlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_resource(SaveFile(self.file.clone()))
+			.add_event::<SaveGame>()
+			// After the planets are spawned, so that they can be replaced
+			.add_startup_system_to_stage(startup_stage::POST_STARTUP, load_game.system())
+			// After every other system, so that saving on quit happens before the app exits
+			.add_system_to_stage(stage::POST_UPDATE, save_game.system());
+	}
+}
+
+/// Bodies of the level, replaced by the ones of the save.
+type LevelBodies = Or<(With<Planet>, With<Moon>, With<GravityWell>)>;
+
+#[allow(clippy::too_many_arguments)]
+pub fn load_game(
+	commands: &mut Commands,
+	save_file: Res<SaveFile>,
+	mut levels: ResMut<Levels>,
+	mut level_time: ResMut<LevelTime>,
+	mut rng: ResMut<GameRng>,
+	mut tick: ResMut<Tick>,
+	mut scheduler: ResMut<SpawnScheduler>,
+	mut director: ResMut<SpawnDirector>,
+	bodies: Query<Entity, LevelBodies>,
+) {
+	let path = match &save_file.0 {
+		Some(path) => path,
+		None => return,
+	};
+	let save = match resume(path, levels.len()) {
+		Some(save) => save,
+		None => return,
+	};
+
+	levels.current = save.level;
+	level_time.0 = save.level_time;
+	*rng = save.rng;
+	tick.0 = save.tick;
+	*scheduler = save.spawn_scheduler;
+	*director = save.spawn_director;
+
+	for body in bodies.iter() {
+		commands.despawn_recursive(body);
+	}
+	spawn_gravity_wells(commands, levels.current());
+	for planet in save.planets {
+		let transform = planet.body.transform();
+		let entity = spawn_planet(
+			commands,
+			transform,
+			planet.body.angular_velocity,
+			planet.body.radius,
+			planet.mass,
+			planet.sprite,
+		);
+		commands.insert(
+			entity,
+			(
+				Biomass(planet.biomass),
+				CurrentIngredients(planet.current_ingredients),
+			),
+		);
+		for moon in planet.moons {
+			let orbit = Orbit {
+				center: entity,
+				distance: moon.distance,
+				angle: moon.angle,
+				angular_speed: moon.angular_speed,
+			};
+			spawn_moon(
+				commands,
+				transform.translation.truncate(),
+				orbit,
+				moon.radius,
+				moon.mass,
+			);
+		}
+	}
+	for SavedAsteroid { kind, body } in save.asteroids {
+		spawn_asteroid(
+			commands,
+			body.transform(),
+			body.velocity.into(),
+			body.angular_velocity,
+			body.radius,
+			kind,
+		);
+	}
+
+	info!("resumed game from `{}`", path.display());
+}
+
+/// What a planet is saved with.
+type PlanetState<'a> = (
+	Entity,
+	&'a Transform,
+	&'a AngularVelocity,
+	&'a Radius,
+	&'a Mass,
+	&'a PlanetSprite,
+	&'a Biomass,
+	&'a CurrentIngredients,
+);
+
+#[allow(clippy::too_many_arguments)]
+pub fn save_game(
+	mut save_reader: Local<EventReader<SaveGame>>,
+	save_requests: Res<Events<SaveGame>>,
+	save_file: Res<SaveFile>,
+	levels: Res<Levels>,
+	level_time: Res<LevelTime>,
+	rng: Res<GameRng>,
+	tick: Res<Tick>,
+	scheduler: Res<SpawnScheduler>,
+	director: Res<SpawnDirector>,
+	planets: Query<PlanetState, With<Planet>>,
+	moons: Query<(&Orbit, &Radius, &Mass), With<Moon>>,
+	asteroids: Query<(&Transform, &Velocity, &AngularVelocity, &Radius, &Asteroid)>,
+) {
+	if save_reader.iter(&save_requests).count() == 0 {
+		return;
+	}
+	let path = match &save_file.0 {
+		Some(path) => path,
+		None => return,
+	};
+
+	let save = SavedGame {
+		version: SAVE_VERSION,
+		level: levels.current,
+		level_time: level_time.0,
+		tick: tick.0,
+		spawn_scheduler: scheduler.clone(),
+		spawn_director: director.clone(),
+		rng: rng.clone(),
+		planets: planets
+			.iter()
+			.map(
+				|(
+					planet,
+					transform,
+					AngularVelocity(av),
+					Radius(radius),
+					Mass(mass),
+					PlanetSprite(sprite),
+					Biomass(biomass),
+					CurrentIngredients(current_ingredients),
+				)| SavedPlanet {
+					body: SavedBody::new(transform, Vec2::zero(), *av, *radius),
+					mass: *mass,
+					sprite: sprite.clone(),
+					biomass: *biomass,
+					current_ingredients: *current_ingredients,
+					moons: moons
+						.iter()
+						.filter(|(orbit, _, _)| orbit.center == planet)
+						.map(|(orbit, Radius(radius), Mass(mass))| SavedMoon {
+							radius: *radius,
+							mass: *mass,
+							distance: orbit.distance,
+							angle: orbit.angle,
+							angular_speed: orbit.angular_speed,
+						})
+						.collect(),
+				},
+			)
+			.collect(),
+		asteroids: asteroids
+			.iter()
+			.map(
+				|(
+					transform,
+					Velocity(velocity),
+					AngularVelocity(av),
+					Radius(radius),
+					Asteroid(kind),
+				)| {
+					SavedAsteroid {
+						kind: *kind,
+						body: SavedBody::new(transform, *velocity, *av, *radius),
+					}
+				},
+			)
+			.collect(),
+	};
+
+	match save.write(path) {
+		Ok(()) => info!("saved game to `{}`", path.display()),
+		Err(err) => warn!("could not save game to `{}`: {}", path.display(), err),
+	}
+}
+
+/// Saves the game when F5 is pressed.
+pub fn save_on_demand(keyboard_input: Res<Input<KeyCode>>, mut save: ResMut<Events<SaveGame>>) {
+	if keyboard_input.just_pressed(KeyCode::F5) {
+		save.send(SaveGame);
+	}
+}
+
+/// Saves the game when the window is closed.
+pub fn save_on_quit(
+	mut close_reader: Local<EventReader<WindowCloseRequested>>,
+	close_requests: Res<Events<WindowCloseRequested>>,
+	mut exit_reader: Local<EventReader<AppExit>>,
+	exits: Res<Events<AppExit>>,
+	mut save: ResMut<Events<SaveGame>>,
+) {
+	if close_reader.iter(&close_requests).next().is_some()
+		|| exit_reader.iter(&exits).next().is_some()
+	{
+		save.send(SaveGame);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn save_path(name: &str) -> PathBuf {
+		std::env::temp_dir().join(format!("biomass-{}-{}.ron", name, std::process::id()))
+	}
+
+	fn new_game(version: u32) -> SavedGame {
+		SavedGame {
+			version,
+			level: 0,
+			level_time: 0.,
+			tick: 0,
+			spawn_scheduler: SpawnScheduler::default(),
+			spawn_director: SpawnDirector::default(),
+			rng: GameRng::from_seed(42),
+			planets: vec![],
+			asteroids: vec![],
+		}
+	}
+
+	#[test]
+	fn resumes_a_save() {
+		let path = save_path("resumes-a-save");
+		new_game(SAVE_VERSION).write(&path).unwrap();
+
+		let save = resume(&path, 1).expect("the save should be resumed");
+		fs::remove_file(&path).unwrap();
+		assert_eq!(save.version, SAVE_VERSION);
+		assert_eq!(save.rng.seed(), 42);
+	}
+
+	#[test]
+	fn rejects_a_save_from_another_version() {
+		let path = save_path("rejects-a-save-from-another-version");
+		new_game(SAVE_VERSION - 1).write(&path).unwrap();
+
+		let result = SavedGame::load(&path);
+		fs::remove_file(&path).unwrap();
+		let old = SAVE_VERSION - 1;
+		assert!(matches!(result, Err(SaveError::UnsupportedVersion(version)) if version == old));
+	}
+
+	#[test]
+	fn moves_a_save_that_cannot_be_loaded_aside() {
+		let path = save_path("moves-a-save-that-cannot-be-loaded-aside");
+		let backup = path.with_extension("bak");
+		fs::write(&path, "(version: 1, tick: ").unwrap();
+
+		assert!(resume(&path, 1).is_none());
+		let contents = fs::read_to_string(&backup);
+		fs::remove_file(&backup).unwrap();
+		assert!(!path.exists());
+		assert_eq!(contents.unwrap(), "(version: 1, tick: ");
+	}
+	#[test]
+	fn moves_a_save_from_an_unknown_level_aside() {
+		let path = save_path("moves-a-save-from-an-unknown-level-aside");
+		let backup = path.with_extension("bak");
+		let mut save = new_game(SAVE_VERSION);
+		save.level = 1;
+		save.write(&path).unwrap();
+
+		assert!(resume(&path, 1).is_none());
+		fs::remove_file(&backup).unwrap();
+		assert!(!path.exists());
+	}
+}
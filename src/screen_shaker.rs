@@ -1,14 +1,21 @@
+// `#[derive(Bundle)]` forgets the components it moves into the world
+#![allow(clippy::forget_non_drop)]
+
 use bevy::prelude::*;
 
-use crate::constants::{
-	SCREEN_SHAKE_DAMPENING, SCREEN_SHAKE_INTENSITY, SCREEN_SHAKE_SPEED, SCREEN_SHAKE_TIMER_DURATION,
-};
+use crate::{bodies::Impact, config::GameConfig, time_scale::TimeScale};
 
 pub struct ScreenShakePlugin;
 
 impl Plugin for ScreenShakePlugin {
 	fn build(&self, app: &mut AppBuilder) {
-		app.add_resource(ScreenShakeTimer::new(SCREEN_SHAKE_TIMER_DURATION))
+		let duration = app
+			.resources()
+			.get::<GameConfig>()
+			.expect("the config plugin must be added before the screen shake plugin")
+			.screen_shake_duration();
+		app.add_resource(ScreenShakeTimer::new(duration))
+			.add_system(shake_on_impact.system())
 			.add_system(screen_shaker.system());
 	}
 }
@@ -35,25 +42,48 @@ impl ScreenShakeTimer {
 	}
 }
 
+pub fn shake_on_impact(
+	mut impact_reader: Local<EventReader<Impact>>,
+	impacts: Res<Events<Impact>>,
+	mut timer: ResMut<ScreenShakeTimer>,
+	mut query: Query<&mut ScreenShaker>,
+) {
+	for impact in impact_reader.iter(&impacts) {
+		// Reset screen shaker timer & set direction to velocity of the asteroid
+		for mut screen_shaker in query.iter_mut() {
+			screen_shaker.direction = impact.velocity.normalize();
+			timer.0.reset();
+		}
+	}
+}
+
 pub fn screen_shaker(
 	time: Res<Time>,
+	time_scale: Res<TimeScale>,
+	config: Res<GameConfig>,
 	mut timer: ResMut<ScreenShakeTimer>,
 	mut query: Query<(&mut Transform, &ScreenShaker)>,
 ) {
+	// Follow changes to the dampening in the config
+	let duration = config.screen_shake_duration();
+	if timer.0.duration() != duration {
+		timer.0.set_duration(duration);
+	}
+
 	// Compute offset based on elapsed time
 	let offset = if !timer.0.finished() {
-		timer.0.tick(time.delta_seconds());
+		timer.0.tick(time_scale.scale(time.delta_seconds()));
 		// Offset is a cosine weighted by a negative exponential
 		let elapsed = timer.0.elapsed();
-		(-elapsed * SCREEN_SHAKE_DAMPENING).exp()
-			* (std::f32::consts::TAU * elapsed * SCREEN_SHAKE_SPEED).cos()
+		(-elapsed * config.screen_shake_dampening).exp()
+			* (std::f32::consts::TAU * elapsed * config.screen_shake_speed).cos()
 	} else {
 		0.
 	};
 
 	// Apply offset to camera
 	for (mut transform, ScreenShaker { direction }) in query.iter_mut() {
-		let displacement = offset * SCREEN_SHAKE_INTENSITY * *direction;
+		let displacement = offset * config.screen_shake_intensity * *direction;
 		transform.translation = displacement.extend(0.);
 	}
 }
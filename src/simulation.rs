@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+	asset_path,
+	bodies::{self, Impact, StageChanged},
+	collisions::asteroid_collisions,
+	config::{ConfigPlugin, GameConfig},
+	constants::RECIPES_PATH,
+	level::LevelPlugin,
+	physics::{PhysicsPlugin, FIXED_UPDATE},
+	recipes::Recipes,
+	replay::{Recorder, Replay},
+	rng::GameRng,
+	save::SavePlugin,
+	slingshot::SlingshotPlugin,
+	spawning::{SpawnDirector, SpawnScheduler},
+	state::{AppState, StatePlugin},
+	time_scale::TimeScalePlugin,
+};
+
+/// The rules of the game: levels, physics, spawning, collisions and evolution of the planets.
+///
+/// This plugin doesn't render anything nor read any input, so that it can run headless with
+/// `MinimalPlugins`.  On its own, the game starts right away; the presentation starts it from the
+/// main menu instead.
+#[derive(Default)]
+pub struct SimulationPlugin {
+	/// Seed of the random number generator, which takes precedence over the one in the config.
+	pub seed: Option<u64>,
+	/// Index of the level to start with, unless a replay is played back.
+	pub level: Option<usize>,
+	/// File to record the slingshot inputs into.
+	pub record: Option<PathBuf>,
+	/// Replay file to play back, whose seed takes precedence over every other.
+	pub replay: Option<PathBuf>,
+	/// File to resume the game from and save it to.
+	pub save: Option<PathBuf>,
+}
+
+impl Plugin for SimulationPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		let recipes = Recipes::load(asset_path(RECIPES_PATH)).unwrap_or_else(|err| {
+			panic!("invalid recipes in `{}`: {}", RECIPES_PATH, err);
+		});
+
+		// Replay
+		let mut replay = self.replay.as_ref().map(|path| {
+			Replay::load(path).unwrap_or_else(|err| {
+				panic!("invalid replay `{}`: {}", path.display(), err);
+			})
+		});
+
+		// Gameplay tuning, as recorded in the replay if there is one.  The config file isn't
+		// reloaded while recording, since the replay only holds the config it started with.
+		match replay.as_mut().and_then(|replay| replay.config.take()) {
+			Some(config) => app.add_resource(config),
+			None => app.add_plugin(ConfigPlugin {
+				watch: self.record.is_none(),
+			}),
+		};
+
+		// Randomness, seeded from the replay, the command line, the config, or else at random
+		let config_seed = app.resources().get::<GameConfig>().unwrap().seed;
+		let seed = replay
+			.as_ref()
+			.map(|replay| replay.seed)
+			.or(self.seed)
+			.or(config_seed)
+			.unwrap_or_else(|| rand::thread_rng().gen());
+		info!("seed: {}", seed);
+
+		let level = replay
+			.as_ref()
+			.map(|replay| replay.level)
+			.or(self.level)
+			.unwrap_or(0);
+
+		// Recording
+		let recorder = match &self.record {
+			Some(path) => {
+				let config = app.resources().get::<GameConfig>().unwrap();
+				Recorder::create(path, seed, level, &config).unwrap_or_else(|err| {
+					panic!("could not record to `{}`: {}", path.display(), err);
+				})
+			}
+			None => Recorder::disabled(),
+		};
+
+		app.add_resource(GameRng::from_seed(seed))
+			.add_resource(replay.unwrap_or_else(Replay::empty))
+			.add_resource(recorder)
+			// Physics
+			.add_plugin(TimeScalePlugin)
+			.add_plugin(PhysicsPlugin)
+			// Game state, which needs the fixed update stage to exist
+			.add_plugin(StatePlugin {
+				initial: AppState::Playing,
+			})
+			// Levels, which refer to recipes
+			.add_resource(recipes)
+			.add_plugin(LevelPlugin { level })
+			// Asteroid stuff
+			.add_event::<Impact>()
+			.add_event::<StageChanged>()
+			.add_system_to_stage(FIXED_UPDATE, bodies::collision.system())
+			.add_system_to_stage(FIXED_UPDATE, asteroid_collisions.system())
+			.add_resource(SpawnDirector::default())
+			.add_resource(SpawnScheduler::default())
+			.add_system_to_stage(FIXED_UPDATE, bodies::asteroid_spawner.system())
+			.add_system_to_stage(FIXED_UPDATE, bodies::asteroid_despawner.system())
+			// Slingshot
+			.add_plugin(SlingshotPlugin)
+			// Saving
+			.add_plugin(SavePlugin {
+				file: self.save.clone(),
+			});
+	}
+}
@@ -1,92 +1,390 @@
-use bevy::prelude::*;
+use bevy::{
+	input::touch::Touch,
+	prelude::*,
+	render::{camera::Camera, draw::Visible},
+};
 use mouse_tracking::{MousePosPlugin, MousePosWorld};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-	bodies::{Asteroid, Radius},
-	constants::{SLINGSHOT_MAX_LENGTH, SLINGSHOT_STRENGTH},
-	physics::Velocity,
+	atlas::SpriteAtlas,
+	bodies::Asteroid,
+	config::GameConfig,
+	constants::ASTEROID_SPRITES,
+	loading::GameAssets,
+	physics::{BodyState, Tick, Velocity, FIXED_UPDATE},
+	presentation::sprite_sheet,
+	replay::{Recorder, Replay},
+	spatial::SpatialGrid,
+	state::AppState,
 };
 
+/// Marks the camera looking at the game, as opposed to the ones drawing the user interface.
+pub struct WorldCamera;
+
+/// Pointer of the mouse; touches are identified by their own id.
+pub const MOUSE_POINTER: u64 = u64::MAX;
+
+/// A slingshot pulling on `target`, held by `pointer` and aimed at `aim` in world coordinates.
 pub struct Slingshot {
 	pub target: Entity,
+	pub pointer: u64,
+	pub aim: Vec2,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum SlingshotAction {
+	Press,
+	Drag,
+	Release,
+	/// The pointer went away without releasing, e.g. a touch interrupted by the system.
+	Cancel,
 }
 
+/// Something the player did with the slingshot using a pointer, at a position in world
+/// coordinates.
+#[derive(Clone, Copy, Debug)]
+pub struct SlingshotInput {
+	pub pointer: u64,
+	pub action: SlingshotAction,
+	pub position: Vec2,
+}
+
+/// Sent when a slingshot is released, rather than cancelled.
+pub struct SlingshotReleased {
+	pub asteroid: Entity,
+	/// How far the slingshot was drawn, from 0 to 1.
+	pub tension: f32,
+}
+
+/// Inputs waiting to be applied on the next fixed step.
+#[derive(Default)]
+pub struct SlingshotInputs(pub Vec<SlingshotInput>);
+
+/// Rules of the slingshot.  Inputs are applied on fixed steps, so that they can be replayed
+/// exactly.
 pub struct SlingshotPlugin;
 
 impl Plugin for SlingshotPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_resource(SlingshotInputs::default())
+			.add_event::<SlingshotReleased>()
+			.add_system_to_stage(FIXED_UPDATE, apply_slingshot_inputs.system());
+	}
+}
+
+/// Mouse and touch controls, and display of the slingshot.
+pub struct SlingshotControlsPlugin;
+
+impl Plugin for SlingshotControlsPlugin {
 	fn build(&self, app: &mut AppBuilder) {
 		app
 			// Mouse stuff
 			.add_plugin(MousePosPlugin::Orthographic)
+			.add_system(mouse_slingshot_input.system())
+			.add_system(touch_slingshot_input.system())
 			// Slingshot stuff
-			.add_system(load_slingshot.system())
-			.add_system(update_slingshot.system())
-			.add_system(release_slingshot.system());
+			.add_system(highlight_hovered_asteroid.system())
+			.add_system(attach_slingshot_sprites.system())
+			.add_system(update_slingshot.system());
 	}
 }
 
-pub fn load_slingshot(
-	commands: &mut Commands,
-	mut materials: ResMut<Assets<ColorMaterial>>,
+/// Velocity added to an asteroid at `position` when a slingshot aimed at `aim` is released.
+pub fn slingshot_velocity(config: &GameConfig, position: Vec2, aim: Vec2) -> Vec2 {
+	let r = position - aim;
+	let r = r.normalize() * r.length().min(config.slingshot_max_length);
+	config.slingshot_strength * r
+}
+
+/// How far a slingshot aimed at `aim` is drawn from an asteroid at `position`, from 0 to 1.
+pub fn slingshot_tension(config: &GameConfig, position: Vec2, aim: Vec2) -> f32 {
+	(position - aim).length().min(config.slingshot_max_length) / config.slingshot_max_length
+}
+
+/// Distance from `point` to the track covered by an asteroid over the last `lead` seconds.
+fn distance_to_track(point: Vec2, body: BodyState, lead: f32) -> f32 {
+	let start = body.position - lead * body.velocity;
+	let track = body.position - start;
+	let along = if track.length_squared() > 0. {
+		((point - start).dot(track) / track.length_squared()).clamp(0., 1.)
+	} else {
+		0.
+	};
+	(start + along * track - point).length()
+}
+
+/// The asteroid grabbed by a pointer at `point`: the nearest one within the pick margin, apart from
+/// the `excluded` ones.
+///
+/// Asteroids are measured from where they were a moment ago as well, so that quickly moving
+/// ones can still be caught by a slightly late click.
+pub fn pick_asteroid(
+	config: &GameConfig,
+	grid: &SpatialGrid,
+	point: Vec2,
+	excluded: &[Entity],
+) -> Option<Entity> {
+	let reach = config.slingshot_pick_margin + config.slingshot_pick_lead * grid.max_speed();
+	grid.query_circle(point, reach)
+		.into_iter()
+		.filter(|entry| !excluded.contains(&entry.entity))
+		.map(|entry| {
+			let distance = distance_to_track(point, entry.body, config.slingshot_pick_lead);
+			(entry.entity, distance - entry.radius)
+		})
+		.filter(|(_, distance)| *distance <= config.slingshot_pick_margin)
+		.min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+		.map(|(asteroid, _)| asteroid)
+}
+
+pub fn mouse_slingshot_input(
+	mut last_position: Local<Vec2>,
+	state: Res<State<AppState>>,
+	replay: Res<Replay>,
 	mouse_button_input: Res<Input<MouseButton>>,
 	mouse: Res<MousePosWorld>,
-	mut asteroids: Query<(Entity, &Transform, &Radius), With<Asteroid>>,
+	mut inputs: ResMut<SlingshotInputs>,
 ) {
-	if mouse_button_input.just_pressed(MouseButton::Left) {
-		for (asteroid, transform, radius) in asteroids.iter_mut() {
-			if radius.contains((transform.translation - mouse.0).truncate()) {
-				commands
-					.spawn(SpriteBundle {
-						material: materials.add(Color::rgb(1.0, 1.0, 1.0).into()),
-						transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
-						sprite: Sprite::new(Vec2::new(0.0, 20.0)),
-						..Default::default()
-					})
-					.with(Slingshot { target: asteroid });
-			}
+	if *state.current() != AppState::Playing || replay.is_playing() {
+		return;
+	}
+
+	let position = mouse.0.truncate();
+	let action = if mouse_button_input.just_pressed(MouseButton::Left) {
+		SlingshotAction::Press
+	} else if mouse_button_input.just_released(MouseButton::Left) {
+		SlingshotAction::Release
+	} else if mouse_button_input.pressed(MouseButton::Left) && position != *last_position {
+		SlingshotAction::Drag
+	} else {
+		return;
+	};
+
+	*last_position = position;
+	inputs.0.push(SlingshotInput {
+		pointer: MOUSE_POINTER,
+		action,
+		position,
+	});
+}
+
+/// Converts a position on the window, from its bottom left corner, to world coordinates as seen
+/// by `camera`.
+fn window_to_world(window: &Window, camera: &GlobalTransform, position: Vec2) -> Vec2 {
+	let offset = position - Vec2::new(window.width(), window.height()) / 2.0;
+	camera.mul_vec3(offset.extend(0.0)).truncate()
+}
+
+/// Every touch drives its own slingshot, so that several asteroids can be aimed at once.
+pub fn touch_slingshot_input(
+	state: Res<State<AppState>>,
+	replay: Res<Replay>,
+	touches: Res<Touches>,
+	windows: Res<Windows>,
+	cameras: Query<(&Camera, &GlobalTransform), With<WorldCamera>>,
+	mut inputs: ResMut<SlingshotInputs>,
+) {
+	if *state.current() != AppState::Playing || replay.is_playing() {
+		return;
+	}
+	// Touches don't say which window they are on, but only happen on the primary one
+	let window = match windows.get_primary() {
+		Some(window) => window,
+		None => return,
+	};
+	let camera = match cameras
+		.iter()
+		.find(|(camera, _)| camera.window == window.id())
+	{
+		Some((_, camera)) => camera,
+		None => return,
+	};
+
+	let mut push = |touch: &Touch, action| {
+		inputs.0.push(SlingshotInput {
+			pointer: touch.id(),
+			action,
+			position: window_to_world(window, camera, touch.position()),
+		});
+	};
+
+	for touch in touches.iter_just_pressed() {
+		push(touch, SlingshotAction::Press);
+	}
+	for touch in touches.iter() {
+		if !touches.just_pressed(touch.id()) && touch.position() != touch.previous_position() {
+			push(touch, SlingshotAction::Drag);
 		}
 	}
+	for touch in touches.iter_just_released() {
+		push(touch, SlingshotAction::Release);
+	}
+	for touch in touches.iter_just_cancelled() {
+		push(touch, SlingshotAction::Cancel);
+	}
 }
 
-pub fn update_slingshot(
-	mouse_button_input: Res<Input<MouseButton>>,
-	mouse: Res<MousePosWorld>,
-	mut asteroids: Query<&Transform, With<Asteroid>>,
-	mut slingshots: Query<(&Slingshot, &mut Sprite, &mut Transform)>,
+#[allow(clippy::too_many_arguments)]
+pub fn apply_slingshot_inputs(
+	commands: &mut Commands,
+	config: Res<GameConfig>,
+	tick: Res<Tick>,
+	mut inputs: ResMut<SlingshotInputs>,
+	mut replay: ResMut<Replay>,
+	mut recorder: ResMut<Recorder>,
+	mut releases: ResMut<Events<SlingshotReleased>>,
+	grid: Res<SpatialGrid>,
+	mut asteroids: Query<(&Transform, &mut Velocity), With<Asteroid>>,
+	mut slingshots: Query<(Entity, &mut Slingshot)>,
 ) {
-	if mouse_button_input.pressed(MouseButton::Left) {
-		if let Some((slingshot, mut slingshot_sprite, mut slingshot_transform)) =
-			slingshots.iter_mut().next()
-		{
-			if let Ok(asteroid) = asteroids.get_mut(slingshot.target) {
-				let r = (asteroid.translation - mouse.0).truncate();
-				let length = r.length().min(SLINGSHOT_MAX_LENGTH);
-				let angle = r.y.atan2(r.x);
-				let r = r.normalize() * length;
-				let position = asteroid.translation.truncate() - r / 2.0;
-				*slingshot_transform = Transform::from_translation(position.extend(0.0));
-				slingshot_transform.rotate(Quat::from_rotation_z(angle));
-				slingshot_sprite.size.x = length;
+	let inputs = if replay.is_playing() {
+		replay.take_inputs(tick.0)
+	} else {
+		inputs.0.drain(..).collect()
+	};
+
+	// Asteroids can only be grabbed by one pointer at a time
+	let mut grabbed: Vec<Entity> = slingshots
+		.iter_mut()
+		.map(|(_, slingshot)| slingshot.target)
+		.collect();
+
+	for input in inputs {
+		recorder.record(tick.0, &input);
+
+		match input.action {
+			SlingshotAction::Press => {
+				if let Some(asteroid) = pick_asteroid(&config, &grid, input.position, &grabbed) {
+					commands.spawn((Slingshot {
+						target: asteroid,
+						pointer: input.pointer,
+						aim: input.position,
+					},));
+					grabbed.push(asteroid);
+				}
+			}
+			SlingshotAction::Drag => {
+				for (_, mut slingshot) in slingshots.iter_mut() {
+					if slingshot.pointer == input.pointer {
+						slingshot.aim = input.position;
+					}
+				}
+			}
+			SlingshotAction::Release | SlingshotAction::Cancel => {
+				for (entity, slingshot) in slingshots.iter_mut() {
+					if slingshot.pointer != input.pointer {
+						continue;
+					}
+					if input.action == SlingshotAction::Release {
+						if let Ok((transform, mut velocity)) = asteroids.get_mut(slingshot.target) {
+							let position = transform.translation.truncate();
+							velocity.0 += slingshot_velocity(&config, position, input.position);
+							releases.send(SlingshotReleased {
+								asteroid: slingshot.target,
+								tension: slingshot_tension(&config, position, input.position),
+							});
+						}
+					}
+					commands.despawn(entity);
+					grabbed.retain(|asteroid| *asteroid != slingshot.target);
+				}
 			}
 		}
 	}
 }
 
-pub fn release_slingshot(
+/// Outline drawn behind the asteroid that a click would grab.
+pub struct PickHighlight;
+
+#[allow(clippy::too_many_arguments)]
+pub fn highlight_hovered_asteroid(
 	commands: &mut Commands,
-	mouse_button_input: Res<Input<MouseButton>>,
+	config: Res<GameConfig>,
+	state: Res<State<AppState>>,
+	replay: Res<Replay>,
 	mouse: Res<MousePosWorld>,
-	mut asteroids: Query<(&Transform, &mut Velocity), With<Asteroid>>,
-	slingshots: Query<(Entity, &Slingshot)>,
+	game_assets: Res<GameAssets>,
+	atlas: Res<SpriteAtlas>,
+	grid: Res<SpatialGrid>,
+	asteroids: Query<(&Transform, &Asteroid)>,
+	slingshots: Query<&Slingshot>,
+	mut highlights: Query<
+		(&mut Transform, &mut TextureAtlasSprite, &mut Visible),
+		With<PickHighlight>,
+	>,
 ) {
-	if mouse_button_input.just_released(MouseButton::Left) {
-		if let Some((entity, slingshot)) = slingshots.iter().next() {
-			if let Ok((transform, mut velocity)) = asteroids.get_mut(slingshot.target) {
-				let r = (transform.translation - mouse.0).truncate();
-				let r = r.normalize() * r.length().min(SLINGSHOT_MAX_LENGTH);
-				velocity.0 += SLINGSHOT_STRENGTH * r;
-			}
-			commands.despawn(entity);
+	let aiming = slingshots
+		.iter()
+		.any(|slingshot| slingshot.pointer == MOUSE_POINTER);
+	let hovered = if *state.current() != AppState::Playing || replay.is_playing() || aiming {
+		None
+	} else {
+		pick_asteroid(&config, &grid, mouse.0.truncate(), &[])
+			.and_then(|asteroid| asteroids.get(asteroid).ok())
+	};
+
+	match (hovered, highlights.iter_mut().next()) {
+		(Some((asteroid, Asteroid(kind))), Some((mut transform, mut sprite, mut visible))) => {
+			*transform = highlight_transform(asteroid);
+			sprite.index = atlas.sprite(ASTEROID_SPRITES[*kind]);
+			visible.is_visible = true;
+		}
+		(Some((asteroid, Asteroid(kind))), None) => {
+			let mut highlight = sprite_sheet(
+				&game_assets,
+				atlas.sprite(ASTEROID_SPRITES[*kind]),
+				highlight_transform(asteroid),
+			);
+			highlight.sprite.color = Color::rgba(1.0, 1.0, 1.0, 0.5);
+			commands.spawn(highlight).with(PickHighlight);
+		}
+		(None, Some((_, _, mut visible))) => visible.is_visible = false,
+		(None, None) => {}
+	}
+}
+
+/// A slightly bigger copy of the asteroid, right behind it.
+fn highlight_transform(asteroid: &Transform) -> Transform {
+	let mut transform = *asteroid;
+	transform.translation.z -= 0.1;
+	transform.scale *= 1.3;
+	transform
+}
+
+pub fn attach_slingshot_sprites(
+	commands: &mut Commands,
+	mut materials: ResMut<Assets<ColorMaterial>>,
+	slingshots: Query<Entity, Added<Slingshot>>,
+) {
+	for slingshot in slingshots.iter() {
+		commands.insert(
+			slingshot,
+			SpriteBundle {
+				material: materials.add(Color::rgb(1.0, 1.0, 1.0).into()),
+				transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+				sprite: Sprite::new(Vec2::new(0.0, 20.0)),
+				..Default::default()
+			},
+		);
+	}
+}
+
+pub fn update_slingshot(
+	config: Res<GameConfig>,
+	mut asteroids: Query<&Transform, With<Asteroid>>,
+	mut slingshots: Query<(&Slingshot, &mut Sprite, &mut Transform)>,
+) {
+	for (slingshot, mut slingshot_sprite, mut slingshot_transform) in slingshots.iter_mut() {
+		if let Ok(asteroid) = asteroids.get_mut(slingshot.target) {
+			let r = asteroid.translation.truncate() - slingshot.aim;
+			let length = r.length().min(config.slingshot_max_length);
+			let angle = r.y.atan2(r.x);
+			let r = r.normalize() * length;
+			let position = asteroid.translation.truncate() - r / 2.0;
+			*slingshot_transform = Transform::from_translation(position.extend(0.0));
+			slingshot_transform.rotate(Quat::from_rotation_z(angle));
+			slingshot_sprite.size.x = length;
 		}
 	}
 }
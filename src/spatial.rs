@@ -0,0 +1,421 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+	bodies::{Asteroid, Radius},
+	constants::{ASTEROID_GRAVITY_TOP_CELLS, SPATIAL_GRID_CELL_SIZE},
+	physics::{Attractor, BodyState, Velocity},
+};
+
+/// An asteroid as it was when the grid was last rebuilt.
+#[derive(Clone, Copy)]
+pub struct GridEntry {
+	pub entity: Entity,
+	pub body: BodyState,
+	pub radius: f32,
+}
+
+impl GridEntry {
+	fn overlaps(&self, position: Vec2, radius: f32) -> bool {
+		(self.body.position - position).length() <= self.radius + radius
+	}
+}
+
+type Cell = (i32, i32);
+
+/// Uniform grid of the asteroids, rebuilt on every fixed step, to find the ones close to a point or
+/// to each other without going through all of them.
+///
+/// Asteroids are listed in every cell their bounding box touches.
+#[derive(Default)]
+pub struct SpatialGrid {
+	entries: Vec<GridEntry>,
+	cells: HashMap<Cell, Vec<usize>>,
+	max_speed: f32,
+}
+
+fn cell_of(position: Vec2) -> Cell {
+	(
+		(position.x / SPATIAL_GRID_CELL_SIZE).floor() as i32,
+		(position.y / SPATIAL_GRID_CELL_SIZE).floor() as i32,
+	)
+}
+
+/// First and last cells touched by the bounding box of a circle.
+fn cell_range(position: Vec2, radius: f32) -> (Cell, Cell) {
+	(
+		cell_of(position - Vec2::splat(radius)),
+		cell_of(position + Vec2::splat(radius)),
+	)
+}
+
+impl SpatialGrid {
+	pub fn rebuild(&mut self, entries: impl IntoIterator<Item = GridEntry>) {
+		self.entries.clear();
+		self.cells.clear();
+		self.max_speed = 0.;
+
+		for entry in entries {
+			let index = self.entries.len();
+			let (min, max) = cell_range(entry.body.position, entry.radius);
+			for x in min.0..=max.0 {
+				for y in min.1..=max.1 {
+					self.cells.entry((x, y)).or_default().push(index);
+				}
+			}
+			self.max_speed = self.max_speed.max(entry.body.velocity.length());
+			self.entries.push(entry);
+		}
+	}
+
+	/// Speed of the fastest asteroid.
+	pub fn max_speed(&self) -> f32 {
+		self.max_speed
+	}
+
+	/// Asteroids overlapping a circle, in the order they were added to the grid.
+	pub fn query_circle(&self, position: Vec2, radius: f32) -> Vec<GridEntry> {
+		let (min, max) = cell_range(position, radius);
+		let mut found = vec![];
+		for x in min.0..=max.0 {
+			for y in min.1..=max.1 {
+				if let Some(cell) = self.cells.get(&(x, y)) {
+					found.extend(
+						cell.iter()
+							.copied()
+							.filter(|index| self.entries[*index].overlaps(position, radius)),
+					);
+				}
+			}
+		}
+		found.sort_unstable();
+		found.dedup();
+		found.into_iter().map(|index| self.entries[index]).collect()
+	}
+
+	/// Pairs of overlapping asteroids, each pair once, in the order they were added to the grid.
+	pub fn overlapping_pairs(&self) -> Vec<(Entity, Entity)> {
+		let mut pairs = vec![];
+		for (cell, indices) in self.cells.iter() {
+			for (i, a) in indices.iter().enumerate() {
+				let entry_a = &self.entries[*a];
+				let (min_a, _) = cell_range(entry_a.body.position, entry_a.radius);
+				for b in indices[i + 1..].iter() {
+					let entry_b = &self.entries[*b];
+					// Only look at a pair in the first cell both asteroids are in
+					let (min_b, _) = cell_range(entry_b.body.position, entry_b.radius);
+					if (min_a.0.max(min_b.0), min_a.1.max(min_b.1)) != *cell {
+						continue;
+					}
+					if entry_a.overlaps(entry_b.body.position, entry_b.radius) {
+						pairs.push((*a, *b));
+					}
+				}
+			}
+		}
+		// Cells come in no particular order, but the simulation must be reproducible
+		pairs.sort_unstable();
+		pairs
+			.into_iter()
+			.map(|(a, b)| (self.entries[a].entity, self.entries[b].entity))
+			.collect()
+	}
+}
+
+/// Asteroids pulling each other, grouped by cells of the grid so that the pull on an asteroid
+/// costs about as much however many asteroids there are.
+///
+/// The asteroids in the cells around a body pull it one by one.  Further away, cells twice as big
+/// at each level pull it as one, from the center of mass of their asteroids, so that no cell
+/// pulls from closer than it is big.
+#[derive(Default)]
+pub struct AsteroidGravity {
+	/// Asteroids by the cell of the grid their center is in.
+	near: HashMap<Cell, Vec<(Entity, Attractor)>>,
+	/// The asteroids of every cell as one, from the cells of the grid to the few cells holding
+	/// all of them, each level with cells twice as big as the level before.
+	levels: Vec<HashMap<Cell, Attractor>>,
+	/// Cells of the last level, in order.
+	top: Vec<(Cell, Attractor)>,
+	/// What pulls the asteroids of each cell of the grid from further than the cells around it.
+	far: HashMap<Cell, Vec<Attractor>>,
+}
+
+/// Attractors as one, at their center of mass.
+fn center_of_mass(attractors: impl IntoIterator<Item = Attractor>) -> Attractor {
+	let (mass, moment) =
+		attractors
+			.into_iter()
+			.fold((0., Vec2::zero()), |(mass, moment), attractor| {
+				(
+					mass + attractor.mass,
+					moment + attractor.mass * attractor.position,
+				)
+			});
+	Attractor {
+		position: moment / mass,
+		mass,
+		radius: 0.,
+	}
+}
+
+/// Cells twice as big, each holding up to four of the given cells, in order.
+fn parent_cells(cells: &[(Cell, Attractor)]) -> Vec<(Cell, Attractor)> {
+	let mut children: HashMap<Cell, Vec<Attractor>> = HashMap::default();
+	for (cell, attractor) in cells.iter() {
+		children
+			.entry((cell.0 >> 1, cell.1 >> 1))
+			.or_default()
+			.push(*attractor);
+	}
+	let mut parents: Vec<(Cell, Attractor)> = children
+		.into_iter()
+		.map(|(cell, children)| (cell, center_of_mass(children)))
+		.collect();
+	parents.sort_unstable_by_key(|(cell, _)| *cell);
+	parents
+}
+
+fn is_around(cell: Cell, center: Cell) -> bool {
+	(cell.0 - center.0).abs() <= 1 && (cell.1 - center.1).abs() <= 1
+}
+
+impl AsteroidGravity {
+	pub fn new(asteroids: impl IntoIterator<Item = (Entity, Attractor)>) -> Self {
+		let mut near: HashMap<Cell, Vec<(Entity, Attractor)>> = HashMap::default();
+		for (entity, attractor) in asteroids {
+			near.entry(cell_of(attractor.position))
+				.or_default()
+				.push((entity, attractor));
+		}
+
+		// In order, so that the masses add up the same way every time
+		let mut cells: Vec<(Cell, Attractor)> = near
+			.iter()
+			.map(|(cell, asteroids)| {
+				let attractors = asteroids.iter().map(|(_, attractor)| *attractor);
+				(*cell, center_of_mass(attractors))
+			})
+			.collect();
+		cells.sort_unstable_by_key(|(cell, _)| *cell);
+		let mut levels = vec![];
+		loop {
+			levels.push(cells.iter().copied().collect());
+			if cells.len() <= ASTEROID_GRAVITY_TOP_CELLS {
+				break;
+			}
+			cells = parent_cells(&cells);
+		}
+
+		let mut gravity = Self {
+			near,
+			levels,
+			top: cells,
+			far: HashMap::default(),
+		};
+		gravity.far = gravity
+			.near
+			.keys()
+			.map(|cell| (*cell, gravity.far_pulling(*cell)))
+			.collect();
+		gravity
+	}
+
+	/// What pulls a body in a cell of the grid from further than the cells around it.
+	fn far_pulling(&self, cell: Cell) -> Vec<Attractor> {
+		let mut pulling = vec![];
+		let top = self.levels.len() - 1;
+		for (level, cells) in self.levels[..top].iter().enumerate() {
+			// The cells of this level that the level above doesn't cover yet: the ones within the
+			// cells around the parent of the body's cell, but not around the body's cell itself
+			let center = (cell.0 >> level, cell.1 >> level);
+			let parent = (center.0 >> 1, center.1 >> 1);
+			for x in 2 * (parent.0 - 1)..2 * (parent.0 + 2) {
+				for y in 2 * (parent.1 - 1)..2 * (parent.1 + 2) {
+					if is_around((x, y), center) {
+						continue;
+					}
+					if let Some(attractor) = cells.get(&(x, y)) {
+						pulling.push(*attractor);
+					}
+				}
+			}
+		}
+		let center = (cell.0 >> top, cell.1 >> top);
+		pulling.extend(
+			self.top
+				.iter()
+				.filter(|(other, _)| !is_around(*other, center))
+				.map(|(_, attractor)| *attractor),
+		);
+		pulling
+	}
+
+	/// Adds the asteroids pulling a body at `position` to `pulling`, leaving the body itself out.
+	pub fn pulling(&self, body: Entity, position: Vec2, pulling: &mut Vec<Attractor>) {
+		if self.near.is_empty() {
+			return;
+		}
+
+		let cell = cell_of(position);
+		match self.far.get(&cell) {
+			Some(far) => pulling.extend_from_slice(far),
+			None => pulling.extend(self.far_pulling(cell)),
+		}
+		for x in cell.0 - 1..=cell.0 + 1 {
+			for y in cell.1 - 1..=cell.1 + 1 {
+				if let Some(asteroids) = self.near.get(&(x, y)) {
+					pulling.extend(
+						asteroids
+							.iter()
+							.filter(|(entity, _)| *entity != body)
+							.map(|(_, attractor)| *attractor),
+					);
+				}
+			}
+		}
+	}
+}
+
+pub fn rebuild_spatial_grid(
+	mut grid: ResMut<SpatialGrid>,
+	asteroids: Query<(Entity, &Transform, &Velocity, &Radius), With<Asteroid>>,
+) {
+	grid.rebuild(asteroids.iter().map(
+		|(entity, transform, Velocity(velocity), Radius(radius))| GridEntry {
+			entity,
+			body: BodyState {
+				position: transform.translation.truncate(),
+				velocity: *velocity,
+			},
+			radius: *radius,
+		},
+	));
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::{prelude::*, rngs::StdRng};
+
+	use super::*;
+	use crate::physics::gravity_acceleration;
+
+	/// Asteroids of various sizes scattered over a few cells, many of them overlapping each other
+	/// or the borders of cells, on both sides of the axes.
+	fn scattered_entries(count: u32) -> Vec<GridEntry> {
+		let mut rng = StdRng::seed_from_u64(7);
+		(0..count)
+			.map(|id| GridEntry {
+				entity: Entity::new(id),
+				body: BodyState {
+					position: Vec2::new(rng.gen_range(-500.0..500.0), rng.gen_range(-500.0..500.0)),
+					velocity: Vec2::new(rng.gen_range(-100.0..100.0), rng.gen_range(-100.0..100.0)),
+				},
+				radius: rng.gen_range(5.0..60.0),
+			})
+			.collect()
+	}
+
+	fn grid_of(entries: &[GridEntry]) -> SpatialGrid {
+		let mut grid = SpatialGrid::default();
+		grid.rebuild(entries.iter().copied());
+		grid
+	}
+
+	#[test]
+	fn query_circle_matches_brute_force() {
+		let entries = scattered_entries(300);
+		let grid = grid_of(&entries);
+
+		let mut rng = StdRng::seed_from_u64(11);
+		for _ in 0..200 {
+			let position = Vec2::new(rng.gen_range(-600.0..600.0), rng.gen_range(-600.0..600.0));
+			let radius = rng.gen_range(0.0..300.0);
+			let expected: Vec<Entity> = entries
+				.iter()
+				.filter(|entry| entry.overlaps(position, radius))
+				.map(|entry| entry.entity)
+				.collect();
+			let found: Vec<Entity> = grid
+				.query_circle(position, radius)
+				.iter()
+				.map(|entry| entry.entity)
+				.collect();
+			assert_eq!(found, expected);
+		}
+	}
+
+	#[test]
+	fn overlapping_pairs_match_brute_force() {
+		let entries = scattered_entries(300);
+		let mut expected = vec![];
+		for (i, a) in entries.iter().enumerate() {
+			for b in entries[i + 1..].iter() {
+				if a.overlaps(b.body.position, b.radius) {
+					expected.push((a.entity, b.entity));
+				}
+			}
+		}
+
+		assert!(!expected.is_empty());
+		assert_eq!(grid_of(&entries).overlapping_pairs(), expected);
+	}
+
+	fn asteroid_attractors(entries: &[GridEntry]) -> Vec<(Entity, Attractor)> {
+		entries
+			.iter()
+			.map(|entry| {
+				let attractor = Attractor {
+					position: entry.body.position,
+					mass: entry.radius / 100.,
+					radius: entry.radius,
+				};
+				(entry.entity, attractor)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn asteroid_gravity_counts_every_other_asteroid_once() {
+		let asteroids = asteroid_attractors(&scattered_entries(300));
+		let gravity = AsteroidGravity::new(asteroids.iter().copied());
+		let total: f32 = asteroids.iter().map(|(_, attractor)| attractor.mass).sum();
+
+		for (entity, attractor) in asteroids.iter() {
+			let mut pulling = vec![];
+			gravity.pulling(*entity, attractor.position, &mut pulling);
+			let pulling_mass: f32 = pulling.iter().map(|other| other.mass).sum();
+			assert!((pulling_mass + attractor.mass - total).abs() <= 1e-4 * total);
+		}
+	}
+
+	#[test]
+	fn asteroid_gravity_is_close_to_every_asteroid_pulling() {
+		let asteroids = asteroid_attractors(&scattered_entries(300));
+		let gravity = AsteroidGravity::new(asteroids.iter().copied());
+
+		let every: Vec<Attractor> = asteroids.iter().map(|(_, attractor)| *attractor).collect();
+		let mut rng = StdRng::seed_from_u64(13);
+		for _ in 0..100 {
+			let position = Vec2::new(rng.gen_range(-800.0..800.0), rng.gen_range(-800.0..800.0));
+			let mut pulling = vec![];
+			gravity.pulling(Entity::new(u32::MAX), position, &mut pulling);
+
+			let exact = gravity_acceleration(1., position, &every);
+			let approximate = gravity_acceleration(1., position, &pulling);
+			// Pulls cancel each other out in the middle of the asteroids, so compare the error to
+			// how strong they are rather than to what is left of them
+			let strength: f32 = every
+				.iter()
+				.map(|attractor| gravity_acceleration(1., position, &[*attractor]).length())
+				.sum();
+			let error = (approximate - exact).length() / strength;
+			assert!(
+				error <= 0.05,
+				"{:?} is too far from {:?} at {:?}",
+				approximate,
+				exact,
+				position
+			);
+		}
+	}
+}
@@ -0,0 +1,320 @@
+use std::{f32::consts::TAU, ops::Range};
+
+use rand::{distributions::WeightedIndex, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::GameConfig, constants::N_ASTEROIDS, recipes::Ingredients, rng::GameRng};
+
+/// Decides which kinds of asteroids spawn next, favoring the ones the current recipe still needs.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SpawnDirector {
+	/// Seconds since each kind of asteroid last spawned.
+	since_spawned: [f32; N_ASTEROIDS],
+}
+
+impl SpawnDirector {
+	/// Lets `seconds` of simulation go by.
+	pub fn advance(&mut self, seconds: f32) {
+		for since_spawned in self.since_spawned.iter_mut() {
+			*since_spawned += seconds;
+		}
+	}
+
+	/// Picks the kinds of the asteroids of the next spawn, given the ingredients of the current
+	/// recipe and the ones the planet already got.
+	///
+	/// A spawn is usually a single asteroid, of any kind, but each missing ingredient adds
+	/// `asteroid_spawn_bias` to the weight of its kind.  Every missing kind that didn't spawn for
+	/// `asteroid_starvation_time` seconds spawns instead, all of them at once.  Starvation is only
+	/// checked when a spawn happens, so during a wave a needed kind spawns at most one spawn period
+	/// after it starves.  Lulls hold it back until the first spawn of the next wave though, which
+	/// can come up to `spawn_lull_duration` plus two spawn periods after the last one.
+	pub fn choose(
+		&mut self,
+		config: &GameConfig,
+		rng: &mut GameRng,
+		recipe: &Ingredients,
+		current: &Ingredients,
+	) -> Vec<usize> {
+		let missing: Vec<usize> = recipe
+			.iter()
+			.zip(current.iter())
+			.map(|(needed, got)| needed.saturating_sub(*got))
+			.collect();
+
+		let mut kinds: Vec<usize> = (0..N_ASTEROIDS)
+			.filter(|kind| {
+				missing[*kind] > 0 && self.since_spawned[*kind] >= config.asteroid_starvation_time
+			})
+			.collect();
+		if kinds.is_empty() {
+			let weights = missing
+				.iter()
+				.map(|missing| 1. + config.asteroid_spawn_bias * *missing as f32);
+			// All weights are at least 1, so they can't be invalid
+			kinds.push(rng.sample(WeightedIndex::new(weights).unwrap()));
+		}
+
+		for kind in kinds.iter() {
+			self.since_spawned[*kind] = 0.;
+		}
+		kinds
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum SpawnPhase {
+	/// Asteroids keep coming, sometimes in bursts or showers.
+	Wave,
+	/// A break without new asteroids.
+	Lull,
+}
+
+/// Asteroids spawning in quick succession from the same direction.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Shower {
+	angle: f32,
+	left: usize,
+	next_spawn: f32,
+}
+
+/// Decides when and from where asteroids spawn: waves separated by lulls, getting more intense as
+/// the planet evolves.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SpawnScheduler {
+	phase: SpawnPhase,
+	/// Seconds left in the current phase.
+	phase_left: f32,
+	/// Seconds until the next spawn of the wave.
+	next_spawn: f32,
+	shower: Option<Shower>,
+}
+
+impl Default for SpawnScheduler {
+	/// Starts with a wave right away.
+	fn default() -> Self {
+		Self {
+			phase: SpawnPhase::Lull,
+			phase_left: 0.,
+			next_spawn: 0.,
+			shower: None,
+		}
+	}
+}
+
+impl SpawnScheduler {
+	/// Lets `seconds` of simulation go by at the given stage of evolution, and returns the angles
+	/// from which new asteroids spawn, within the spawn zones if there are any.
+	pub fn advance(
+		&mut self,
+		config: &GameConfig,
+		rng: &mut GameRng,
+		stage: usize,
+		zones: &[Range<f32>],
+		seconds: f32,
+	) -> Vec<f32> {
+		let mut angles = vec![];
+
+		if let Some(shower) = &mut self.shower {
+			shower.next_spawn -= seconds;
+			if shower.next_spawn <= 0. {
+				let spread = config.spawn_shower_spread / 2.;
+				angles.push(shower.angle + rng.gen_range(-spread..=spread));
+				shower.left -= 1;
+				shower.next_spawn += config.spawn_shower_period;
+			}
+			if shower.left == 0 {
+				self.shower = None;
+			}
+		}
+
+		self.phase_left -= seconds;
+		if self.phase_left <= 0. {
+			match self.phase {
+				SpawnPhase::Wave => {
+					self.phase = SpawnPhase::Lull;
+					self.phase_left += config.spawn_lull_duration;
+				}
+				SpawnPhase::Lull => {
+					self.phase = SpawnPhase::Wave;
+					self.phase_left += config.spawn_wave_duration;
+					self.next_spawn = spawn_period(config, stage);
+				}
+			}
+		}
+
+		if self.phase == SpawnPhase::Wave {
+			self.next_spawn -= seconds;
+			if self.next_spawn <= 0. {
+				self.next_spawn += spawn_period(config, stage);
+
+				let roll: f32 = rng.gen();
+				if roll < config.spawn_burst_chance {
+					for _ in 0..config.spawn_burst_size {
+						angles.push(random_angle(rng, zones));
+					}
+				} else if roll < config.spawn_burst_chance + config.spawn_shower_chance
+					&& self.shower.is_none()
+					&& config.spawn_shower_size > 0
+				{
+					self.shower = Some(Shower {
+						angle: random_angle(rng, zones),
+						left: config.spawn_shower_size,
+						next_spawn: 0.,
+					});
+				} else {
+					angles.push(random_angle(rng, zones));
+				}
+			}
+		}
+
+		angles
+	}
+}
+
+/// An angle within one of the zones, each zone as likely as it is wide, or any angle if there are
+/// no zones.
+fn random_angle(rng: &mut GameRng, zones: &[Range<f32>]) -> f32 {
+	if zones.is_empty() {
+		return rng.gen_range(0.0..TAU);
+	}
+	let total: f32 = zones.iter().map(|zone| zone.end - zone.start).sum();
+	let mut roll = rng.gen_range(0.0..total);
+	for zone in zones {
+		let width = zone.end - zone.start;
+		if roll < width {
+			return zone.start + roll;
+		}
+		roll -= width;
+	}
+	// Only reached through rounding errors
+	zones[zones.len() - 1].end
+}
+
+/// Seconds between two spawns of a wave, shorter at later stages of evolution.
+fn spawn_period(config: &GameConfig, stage: usize) -> f32 {
+	config.asteroid_spawn_period / (1. + config.spawn_rate_per_stage * stage as f32)
+}
+
+/// How much faster asteroids fly at a stage of evolution than at the first one.
+pub fn speed_factor(config: &GameConfig, stage: usize) -> f32 {
+	1. + config.asteroid_speed_per_stage * stage as f32
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::constants::DELTA;
+
+	#[test]
+	fn needed_kinds_spawn_before_starving_for_long() {
+		let mut config = GameConfig::from_ron(include_str!("../assets/config.ron")).unwrap();
+		// Leave the guarantee alone to bring the needed kinds
+		config.asteroid_spawn_bias = 0.;
+		let bound = config.asteroid_starvation_time + config.asteroid_spawn_period;
+		let recipe = [1; N_ASTEROIDS];
+		let current = [0; N_ASTEROIDS];
+
+		for seed in 0..20 {
+			let mut rng = GameRng::from_seed(seed);
+			let mut director = SpawnDirector::default();
+			let mut waited = [0.; N_ASTEROIDS];
+			for _ in 0..200 {
+				director.advance(config.asteroid_spawn_period);
+				for waited in waited.iter_mut() {
+					*waited += config.asteroid_spawn_period;
+				}
+				for kind in director.choose(&config, &mut rng, &recipe, &current) {
+					waited[kind] = 0.;
+				}
+				for &waited in waited.iter() {
+					assert!(
+						waited <= bound,
+						"waited {} seconds for a needed kind",
+						waited
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn only_needed_kinds_starve() {
+		let config = GameConfig::from_ron(include_str!("../assets/config.ron")).unwrap();
+		let mut rng = GameRng::from_seed(42);
+		let mut starving = SpawnDirector::default();
+		starving.advance(config.asteroid_starvation_time);
+		let mut recipe = [0; N_ASTEROIDS];
+		recipe[3] = 2;
+		let mut current = [0; N_ASTEROIDS];
+
+		current[3] = 2;
+		let kinds = starving
+			.clone()
+			.choose(&config, &mut rng, &recipe, &current);
+		assert_eq!(kinds.len(), 1);
+		current[3] = 1;
+		let kinds = starving
+			.clone()
+			.choose(&config, &mut rng, &recipe, &current);
+		assert_eq!(kinds, vec![3]);
+	}
+
+	#[test]
+	fn lulls_hold_starved_kinds_back_until_the_next_wave() {
+		let mut config = GameConfig::from_ron(include_str!("../assets/config.ron")).unwrap();
+		config.asteroid_spawn_bias = 0.;
+		let bound = config.asteroid_starvation_time
+			+ config.spawn_lull_duration
+			+ 2. * config.asteroid_spawn_period
+			+ DELTA as f32;
+		let recipe = [1; N_ASTEROIDS];
+		let current = [0; N_ASTEROIDS];
+
+		let mut rng = GameRng::from_seed(42);
+		let mut scheduler = SpawnScheduler::default();
+		let mut director = SpawnDirector::default();
+		let mut waited = [0.; N_ASTEROIDS];
+		let mut longest = 0f32;
+		for _ in 0..(300. / DELTA) as usize {
+			director.advance(DELTA as f32);
+			for waited in waited.iter_mut() {
+				*waited += DELTA as f32;
+				longest = longest.max(*waited);
+			}
+			for _ in scheduler.advance(&config, &mut rng, 0, &[], DELTA as f32) {
+				for kind in director.choose(&config, &mut rng, &recipe, &current) {
+					waited[kind] = 0.;
+				}
+			}
+		}
+		assert!(
+			longest <= bound,
+			"waited {} seconds for a needed kind",
+			longest
+		);
+		assert!(
+			longest > config.asteroid_starvation_time + config.asteroid_spawn_period,
+			"no lull held a starved kind back"
+		);
+	}
+	#[test]
+	fn asteroids_only_come_from_the_spawn_zones() {
+		let zones = [0.5..1.0, 3.0..4.5];
+		let mut rng = GameRng::from_seed(42);
+		let angles: Vec<f32> = (0..1000).map(|_| random_angle(&mut rng, &zones)).collect();
+		assert!(angles
+			.iter()
+			.all(|angle| zones.iter().any(|zone| zone.contains(angle))));
+		// Each zone as likely as it is wide
+		let first = angles
+			.iter()
+			.filter(|angle| zones[0].contains(angle))
+			.count();
+		assert!(
+			(200..300).contains(&first),
+			"{} angles in the first zone",
+			first
+		);
+	}
+}
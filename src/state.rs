@@ -0,0 +1,169 @@
+use bevy::prelude::*;
+
+use crate::{
+	bodies::{spawn_bodies, Asteroid, GravityWell, Impact, Moon, Planet, StageChanged},
+	current_recipe,
+	level::{LevelTime, Levels},
+	physics::FIXED_UPDATE,
+	recipes::{IngredientsComparison, Recipes},
+	slingshot::Slingshot,
+	spawning::{SpawnDirector, SpawnScheduler},
+	Biomass,
+};
+
+pub const APP_STATE_STAGE: &str = "app_state";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AppState {
+	/// Waiting for the assets of the game, before the main menu.
+	Loading,
+	MainMenu,
+	LevelSelect,
+	Playing,
+	Paused,
+	/// Every planet evolved into the goal of the level.
+	Victory,
+	/// An extinction wiped out all life on a planet, or time ran out.
+	GameOver,
+}
+
+/// Sent to start the current level over the next time the game is played.
+pub struct NewGame;
+
+/// Whether the game goes on whatever happens to the planets, as in stress tests.
+pub struct Endless(pub bool);
+
+pub struct StatePlugin {
+	pub initial: AppState,
+}
+
+impl Plugin for StatePlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_resource(State::new(self.initial))
+			.add_resource(Endless(false))
+			// State changes apply before the next fixed step
+			.add_stage_before(
+				FIXED_UPDATE,
+				APP_STATE_STAGE,
+				StateStage::<AppState>::default(),
+			)
+			.add_event::<NewGame>()
+			.on_state_enter(APP_STATE_STAGE, AppState::Playing, new_game.system())
+			.add_system(check_victory.system())
+			.add_system(check_game_over.system())
+			.add_system(check_time_limit.system());
+	}
+}
+
+/// Whether every planet reached the goal of the level.
+fn goal_reached<'a>(
+	recipes: &Recipes,
+	levels: &Levels,
+	mut planets: impl Iterator<Item = &'a Biomass>,
+) -> bool {
+	planets.all(|biomass| current_recipe(recipes, biomass.0) >= levels.current().goal)
+}
+
+pub fn check_victory(
+	mut stage_reader: Local<EventReader<StageChanged>>,
+	stage_changes: Res<Events<StageChanged>>,
+	recipes: Res<Recipes>,
+	levels: Res<Levels>,
+	planets: Query<&Biomass, With<Planet>>,
+	endless: Res<Endless>,
+	mut state: ResMut<State<AppState>>,
+) {
+	// Only once the last planet reaches the goal, so that keeping playing after a victory is
+	// possible
+	let goal = levels.current().goal;
+	let reached = stage_reader
+		.iter(&stage_changes)
+		.filter(|change| change.previous < goal && change.current >= goal)
+		.count();
+	if reached == 0 || endless.0 {
+		return;
+	}
+	if goal_reached(&recipes, &levels, planets.iter()) && *state.current() == AppState::Playing {
+		let _ = state.set_next(AppState::Victory);
+	}
+}
+
+pub fn check_game_over(
+	mut impact_reader: Local<EventReader<Impact>>,
+	impacts: Res<Events<Impact>>,
+	planets: Query<&Biomass, With<Planet>>,
+	endless: Res<Endless>,
+	mut state: ResMut<State<AppState>>,
+) {
+	if endless.0 {
+		return;
+	}
+	for impact in impact_reader.iter(&impacts) {
+		if impact.outcome != Some(IngredientsComparison::TooMany) {
+			continue;
+		}
+		if let Ok(biomass) = planets.get(impact.body) {
+			if biomass.0 <= 1 && *state.current() == AppState::Playing {
+				let _ = state.set_next(AppState::GameOver);
+			}
+		}
+	}
+}
+
+/// Ends the game once the time limit of the level is up, unless the goal was reached.
+pub fn check_time_limit(
+	recipes: Res<Recipes>,
+	levels: Res<Levels>,
+	level_time: Res<LevelTime>,
+	planets: Query<&Biomass, With<Planet>>,
+	endless: Res<Endless>,
+	mut state: ResMut<State<AppState>>,
+) {
+	if endless.0 {
+		return;
+	}
+	let out_of_time = matches!(level_time.left(levels.current()), Some(left) if left <= 0.);
+	if *state.current() != AppState::Playing || !out_of_time {
+		return;
+	}
+	if !goal_reached(&recipes, &levels, planets.iter()) {
+		let _ = state.set_next(AppState::GameOver);
+	}
+}
+
+/// Everything that belongs to the game in progress.
+type GameBodies = Or<(
+	With<Planet>,
+	With<Moon>,
+	With<GravityWell>,
+	With<Asteroid>,
+	With<Slingshot>,
+)>;
+
+/// Restarts the current level and clears the sky if a new game was requested.
+#[allow(clippy::too_many_arguments)]
+pub fn new_game(
+	commands: &mut Commands,
+	mut new_game_reader: Local<EventReader<NewGame>>,
+	new_games: Res<Events<NewGame>>,
+	levels: Res<Levels>,
+	mut level_time: ResMut<LevelTime>,
+	mut scheduler: ResMut<SpawnScheduler>,
+	mut director: ResMut<SpawnDirector>,
+	bodies: Query<Entity, GameBodies>,
+) {
+	if new_game_reader.iter(&new_games).count() == 0 {
+		return;
+	}
+
+	// Along with whatever is drawn over them
+	for entity in bodies.iter() {
+		commands.despawn_recursive(entity);
+	}
+	spawn_bodies(commands, levels.current());
+
+	level_time.0 = 0.;
+
+	*scheduler = SpawnScheduler::default();
+	*director = SpawnDirector::default();
+}
@@ -0,0 +1,72 @@
+use bevy::{
+	diagnostic::{
+		Diagnostics, DiagnosticsPlugin, FrameTimeDiagnosticsPlugin, PrintDiagnosticsPlugin,
+	},
+	prelude::*,
+};
+use rand::prelude::*;
+
+use crate::{
+	bodies::spawn_asteroid,
+	config::GameConfig,
+	constants::{ASTEROID_RADIUS, ASTEROID_SPAWN_DISTANCE, N_ASTEROIDS},
+	level::Levels,
+	rng::GameRng,
+	state::Endless,
+};
+
+/// Number of asteroids the stress test starts with.
+pub struct StressTest(pub usize);
+
+/// Fills the sky with asteroids in orbit around the planets, and logs the frame rate, to check how
+/// the game copes with crowded screens.  The game doesn't end, whatever the asteroids do to the
+/// planets.
+///
+/// `cargo bench --bench stress` times the same crowds headless, step by step.
+pub struct StressPlugin {
+	pub asteroids: usize,
+}
+
+impl Plugin for StressPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		// Headless runs don't have diagnostics otherwise
+		if app.resources().get::<Diagnostics>().is_none() {
+			app.add_plugin(DiagnosticsPlugin);
+		}
+		app.add_resource(StressTest(self.asteroids))
+			// Crowds of asteroids soon wipe out life on the planet
+			.add_resource(Endless(true))
+			.add_plugin(FrameTimeDiagnosticsPlugin)
+			.add_plugin(PrintDiagnosticsPlugin::default())
+			.add_startup_system(spawn_stress_asteroids.system());
+	}
+}
+
+pub fn spawn_stress_asteroids(
+	commands: &mut Commands,
+	stress_test: Res<StressTest>,
+	config: Res<GameConfig>,
+	levels: Res<Levels>,
+	mut rng: ResMut<GameRng>,
+) {
+	// Orbits are computed as if all planets were one at the center
+	let planets = &levels.current().planets;
+	let mass: f32 = planets.iter().map(|planet| planet.mass()).sum();
+	for _ in 0..stress_test.0 {
+		// Clear of the planet, and within the screen
+		let distance = rng.gen_range(200.0..ASTEROID_SPAWN_DISTANCE);
+		let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+		let direction = Vec2::new(angle.cos(), angle.sin());
+		// Fast enough to stay on a circular orbit
+		let velocity = (config.gravity * mass / distance).sqrt() * direction.perp();
+		spawn_asteroid(
+			commands,
+			Transform::from_translation((distance * direction).extend(0.)),
+			velocity,
+			rng.gen_range(config.asteroid_angular_velocity.clone()),
+			ASTEROID_RADIUS,
+			rng.gen_range(0..N_ASTEROIDS),
+		);
+	}
+	info!("spawned {} asteroids for the stress test", stress_test.0);
+}
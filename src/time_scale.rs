@@ -0,0 +1,68 @@
+use bevy::prelude::*;
+
+use crate::{config::GameConfig, constants::TIME_SCALE_EASING, slingshot::Slingshot};
+
+/// How fast the game runs compared to real time: slower while aiming, and faster while the player
+/// fast-forwards.
+///
+/// The simulation, and every animation that goes along with it, must scale their time with this.
+pub struct TimeScale {
+	/// Current scale, easing towards the one wanted.
+	pub current: f32,
+	/// Whether the player wants to fast-forward.
+	pub fast_forward: bool,
+}
+
+impl Default for TimeScale {
+	fn default() -> Self {
+		Self {
+			current: 1.,
+			fast_forward: false,
+		}
+	}
+}
+
+impl TimeScale {
+	/// Game time spent over `seconds` of real time.
+	pub fn scale(&self, seconds: f32) -> f32 {
+		self.current * seconds
+	}
+}
+
+pub struct TimeScalePlugin;
+
+impl Plugin for TimeScalePlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_resource(TimeScale::default())
+			.add_system(update_time_scale.system());
+	}
+}
+
+/// Slows the game down while a slingshot is held, and speeds it up while fast-forwarding unless
+/// the player is aiming.
+pub fn update_time_scale(
+	time: Res<Time>,
+	config: Res<GameConfig>,
+	mut time_scale: ResMut<TimeScale>,
+	slingshots: Query<&Slingshot>,
+) {
+	let target = if slingshots.iter().next().is_some() {
+		config.aiming_time_scale
+	} else if time_scale.fast_forward {
+		config.fast_forward_time_scale
+	} else {
+		1.
+	};
+
+	// Ease in real time, so that it doesn't take longer in slow motion
+	let easing = (TIME_SCALE_EASING * time.delta_seconds()).min(1.);
+	time_scale.current += (target - time_scale.current) * easing;
+}
+
+/// Fast-forwards while space is held.
+pub fn fast_forward_controls(
+	keyboard_input: Res<Input<KeyCode>>,
+	mut time_scale: ResMut<TimeScale>,
+) {
+	time_scale.fast_forward = keyboard_input.pressed(KeyCode::Space);
+}
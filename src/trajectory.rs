@@ -0,0 +1,168 @@
+use bevy::{prelude::*, render::draw::Visible};
+
+use crate::{
+	bodies::{Asteroid, Radius},
+	config::GameConfig,
+	constants::{DELTA, TRAJECTORY_DURATION, TRAJECTORY_MARKER_SIZE, TRAJECTORY_MARKER_SPACING},
+	physics::{
+		asteroid_gravity, predict_trajectory, step, BodyState, Mass, MovingAttractors, Orbit,
+		SimulationClock, Velocity,
+	},
+	slingshot::{slingshot_velocity, Slingshot},
+};
+
+/// Draws where asteroids would go if their slingshot was released now, as a trail of fading
+/// markers.
+pub struct TrajectoryPlugin;
+
+impl Plugin for TrajectoryPlugin {
+	fn build(&self, app: &mut AppBuilder) {
+		app.add_startup_system(setup_trajectory_materials.system())
+			.add_system(draw_trajectories.system());
+	}
+}
+
+pub struct TrajectoryMarker;
+
+/// One material per marker along a trajectory at the default step, more and more transparent.
+pub struct TrajectoryMaterials(Vec<Handle<ColorMaterial>>);
+
+fn trajectory_steps(delta: f32) -> usize {
+	(TRAJECTORY_DURATION / delta) as usize
+}
+
+fn setup_trajectory_materials(
+	commands: &mut Commands,
+	mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+	let markers = trajectory_steps(DELTA as f32) / TRAJECTORY_MARKER_SPACING;
+	let handles = (0..markers)
+		.map(|index| {
+			let alpha = 0.8 * (1. - index as f32 / markers as f32);
+			materials.add(Color::rgba(1.0, 1.0, 1.0, alpha).into())
+		})
+		.collect();
+	commands.insert_resource(TrajectoryMaterials(handles));
+}
+
+/// Positions of an asteroid over the next fixed steps of `dt` seconds if its slingshot was
+/// released now.
+///
+/// The release is only applied on the next fixed step, after the asteroid moved one more step,
+/// so that is where the prediction starts from.
+pub fn predict_release(
+	config: &GameConfig,
+	body: BodyState,
+	radius: f32,
+	aim: Vec2,
+	attractors: &MovingAttractors,
+	dt: f32,
+) -> Vec<Vec2> {
+	let mut attractors = attractors.clone();
+	attractors.advance(dt);
+	let mut released = step(
+		config.integrator,
+		body,
+		config.gravity,
+		&attractors.attractors,
+		dt,
+	);
+	released.velocity += slingshot_velocity(config, released.position, aim);
+
+	let mut trajectory = vec![released.position];
+	trajectory.extend(predict_trajectory(
+		config.integrator,
+		released,
+		radius,
+		config.gravity,
+		&attractors,
+		trajectory_steps(dt) - 1,
+		dt,
+	));
+	trajectory
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn draw_trajectories(
+	commands: &mut Commands,
+	config: Res<GameConfig>,
+	clock: Res<SimulationClock>,
+	trajectory_materials: Res<TrajectoryMaterials>,
+	slingshots: Query<&Slingshot>,
+	asteroids: Query<(Entity, &Transform, &Velocity, &Radius), With<Asteroid>>,
+	attracting: Query<(&Transform, &Mass, &Radius, Option<&Orbit>), Without<Velocity>>,
+	centers: Query<&Transform, Without<Orbit>>,
+	mut markers: Query<
+		(&mut Transform, &mut Visible, &mut Handle<ColorMaterial>),
+		With<TrajectoryMarker>,
+	>,
+) {
+	let attractors = MovingAttractors::new(attracting.iter(), &centers);
+	let other_asteroids = asteroid_gravity(
+		&config,
+		asteroids
+			.iter()
+			.map(|(entity, transform, _, Radius(radius))| {
+				(entity, transform.translation.truncate(), *radius)
+			}),
+	);
+
+	// Markers to draw, with the index of their material
+	let mut points = vec![];
+	for slingshot in slingshots.iter() {
+		if let Ok((_, transform, Velocity(velocity), Radius(radius))) =
+			asteroids.get(slingshot.target)
+		{
+			let body = BodyState {
+				position: transform.translation.truncate(),
+				velocity: *velocity,
+			};
+			// Other asteroids are assumed to stay where they are
+			let mut pulling = attractors.clone();
+			other_asteroids.pulling(slingshot.target, body.position, &mut pulling.attractors);
+
+			let trajectory = predict_release(
+				&config,
+				body,
+				*radius,
+				slingshot.aim,
+				&pulling,
+				clock.delta(),
+			);
+			let last_material = trajectory_materials.0.len() - 1;
+			points.extend(
+				trajectory
+					.into_iter()
+					.skip(TRAJECTORY_MARKER_SPACING - 1)
+					.step_by(TRAJECTORY_MARKER_SPACING)
+					.enumerate()
+					.map(|(index, position)| (position, index.min(last_material))),
+			);
+		}
+	}
+
+	// Reuse the markers of the previous frame, and hide the ones left over
+	let mut points = points.into_iter();
+	for (mut transform, mut visible, mut material) in markers.iter_mut() {
+		match points.next() {
+			Some((position, index)) => {
+				transform.translation = position.extend(0.5);
+				*material = trajectory_materials.0[index].clone();
+				visible.is_visible = true;
+			}
+			None => visible.is_visible = false,
+		}
+	}
+
+	// Spawn new markers for the rest
+	for (position, index) in points {
+		commands
+			.spawn(SpriteBundle {
+				material: trajectory_materials.0[index].clone(),
+				transform: Transform::from_translation(position.extend(0.5)),
+				sprite: Sprite::new(Vec2::splat(TRAJECTORY_MARKER_SIZE)),
+				..Default::default()
+			})
+			.with(TrajectoryMarker);
+	}
+}
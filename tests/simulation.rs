@@ -0,0 +1,320 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use biomass_rs::{
+	bodies::{Asteroid, Planet, Radius},
+	config::GameConfig,
+	constants::{DELTA, INITIAL_BIOMASS},
+	level::{Levels, MoonDescription},
+	physics::{
+		asteroid_gravity, BodyState, Mass, MovingAttractors, Orbit, SimulationClock, Tick,
+		Velocity, FIXED_UPDATE,
+	},
+	rng::GameRng,
+	simulation::SimulationPlugin,
+	slingshot::{Slingshot, SlingshotAction, SlingshotInput, SlingshotInputs, MOUSE_POINTER},
+	trajectory::predict_release,
+	Biomass,
+};
+
+/// Fixed step at which recorded and replayed games are compared, a couple of seconds after the
+/// first asteroid spawns.
+const END: u64 = 240;
+
+/// The rules of the game without any window, advancing by one fixed step per update whatever the
+/// real time.
+fn headless_game(plugin: SimulationPlugin) -> App {
+	let mut builder = App::build();
+	builder
+		.add_plugins(MinimalPlugins)
+		.add_plugin(plugin)
+		.add_resource(EndState::default())
+		.add_system_to_stage(FIXED_UPDATE, capture_end_state.system());
+	let app = builder.app;
+	app.resources.get_mut::<SimulationClock>().unwrap().stepping = true;
+	app
+}
+
+fn step(app: &mut App) {
+	app.resources
+		.get_mut::<SimulationClock>()
+		.unwrap()
+		.requested_steps = 1;
+	app.update();
+}
+
+/// Kind, position and velocity of every asteroid at the end of tick `END`.
+#[derive(Default)]
+struct EndState(Option<Vec<(usize, Vec3, Vec2)>>);
+
+fn capture_end_state(
+	tick: Res<Tick>,
+	mut end_state: ResMut<EndState>,
+	asteroids: Query<(&Asteroid, &Transform, &Velocity)>,
+) {
+	if tick.0 == END {
+		end_state.0 = Some(
+			asteroids
+				.iter()
+				.map(|(Asteroid(kind), transform, Velocity(velocity))| {
+					(*kind, transform.translation, *velocity)
+				})
+				.collect(),
+		);
+	}
+}
+
+/// Steps the game until `done`, calling `play` before every step.
+fn run_until(app: &mut App, done: impl Fn(&App) -> bool, mut play: impl FnMut(&mut App)) {
+	while !done(app) {
+		play(app);
+		step(app);
+	}
+}
+
+/// Steps the game until tick `END` is over, calling `play` before every step.
+fn run_to_end(app: &mut App, play: impl FnMut(&mut App)) -> Vec<(usize, Vec3, Vec2)> {
+	let ended = |app: &App| app.resources.get::<EndState>().unwrap().0.is_some();
+	run_until(app, ended, play);
+	app.resources
+		.get_mut::<EndState>()
+		.unwrap()
+		.0
+		.take()
+		.unwrap()
+}
+
+fn replay_path(name: &str) -> PathBuf {
+	std::env::temp_dir().join(format!("biomass-{}-{}.ron", name, std::process::id()))
+}
+
+#[test]
+fn runs_headless() {
+	let mut app = headless_game(SimulationPlugin {
+		seed: Some(42),
+		..Default::default()
+	});
+	for _ in 0..10 {
+		step(&mut app);
+	}
+
+	assert_eq!(app.resources.get::<Tick>().unwrap().0, 10);
+	let biomass: Vec<u32> = app
+		.world
+		.query_filtered::<&Biomass, With<Planet>>()
+		.map(|biomass| biomass.0)
+		.collect();
+	assert_eq!(biomass, vec![INITIAL_BIOMASS]);
+	assert_eq!(app.resources.get::<GameRng>().unwrap().seed(), 42);
+}
+
+#[test]
+fn same_seed_plays_the_same() {
+	let plugin = || SimulationPlugin {
+		seed: Some(42),
+		..Default::default()
+	};
+	let first = run_to_end(&mut headless_game(plugin()), |_| {});
+	let second = run_to_end(&mut headless_game(plugin()), |_| {});
+
+	assert!(!first.is_empty());
+	assert_eq!(first, second);
+}
+
+#[test]
+fn different_seeds_spawn_differently() {
+	let plugin = |seed| SimulationPlugin {
+		seed: Some(seed),
+		..Default::default()
+	};
+	let first = run_to_end(&mut headless_game(plugin(1)), |_| {});
+	let second = run_to_end(&mut headless_game(plugin(2)), |_| {});
+
+	assert_ne!(first, second);
+}
+
+#[test]
+fn replay_plays_the_same() {
+	let path = replay_path("replay-plays-the-same");
+	let mut recorded = headless_game(SimulationPlugin {
+		seed: Some(42),
+		record: Some(path.clone()),
+		..Default::default()
+	});
+	// Flings the first asteroid away from its course once it spawns
+	let mut steps_since_pressed = None;
+	let mut grabbed = false;
+	let recorded_end = run_to_end(&mut recorded, |app| {
+		let mut inputs = vec![];
+		match steps_since_pressed {
+			None => {
+				if let Some(transform) = app.world.query::<(&Asteroid, &Transform)>().next() {
+					let position = transform.1.translation.truncate();
+					inputs.push((SlingshotAction::Press, position));
+					steps_since_pressed = Some(0);
+				}
+			}
+			Some(steps) => {
+				grabbed |= app.world.query::<&Slingshot>().next().is_some();
+				if steps == 10 {
+					inputs.push((SlingshotAction::Drag, Vec2::new(500., 500.)));
+				} else if steps == 20 {
+					inputs.push((SlingshotAction::Release, Vec2::new(500., 500.)));
+				}
+				steps_since_pressed = Some(steps + 1);
+			}
+		}
+		let mut slingshot_inputs = app.resources.get_mut::<SlingshotInputs>().unwrap();
+		for (action, position) in inputs {
+			slingshot_inputs.0.push(SlingshotInput {
+				pointer: MOUSE_POINTER,
+				action,
+				position,
+			});
+		}
+	});
+	assert!(grabbed, "the asteroid was never grabbed");
+
+	let mut replayed = headless_game(SimulationPlugin {
+		replay: Some(path.clone()),
+		..Default::default()
+	});
+	let replayed_end = run_to_end(&mut replayed, |_| {});
+	std::fs::remove_file(path).unwrap();
+
+	assert!(!recorded_end.is_empty());
+	assert_eq!(replayed.resources.get::<GameRng>().unwrap().seed(), 42);
+	assert_eq!(recorded_end, replayed_end);
+}
+
+/// Trajectory predicted when releasing the first asteroid, and where it actually went.
+#[derive(Default)]
+struct Fling {
+	asteroid: Option<Entity>,
+	predicted: Vec<Vec2>,
+	actual: Vec<Vec2>,
+	over: bool,
+}
+
+/// Grabs the first asteroid, then predicts its trajectory and releases it as soon as it is held,
+/// and follows it until the end of the prediction.  All of this happens on fixed steps, like the
+/// inputs of a player would be applied.
+#[allow(clippy::too_many_arguments)]
+fn fling_first_asteroid(
+	config: Res<GameConfig>,
+	clock: Res<SimulationClock>,
+	mut fling: ResMut<Fling>,
+	mut inputs: ResMut<SlingshotInputs>,
+	asteroids: Query<(Entity, &Transform, &Velocity, &Radius), With<Asteroid>>,
+	slingshots: Query<&Slingshot>,
+	attracting: Query<(&Transform, &Mass, &Radius, Option<&Orbit>), Without<Velocity>>,
+	centers: Query<&Transform, Without<Orbit>>,
+) {
+	let asteroid = match fling.asteroid {
+		Some(asteroid) => asteroid,
+		None => {
+			if let Some((asteroid, transform, ..)) = asteroids.iter().next() {
+				fling.asteroid = Some(asteroid);
+				inputs.0.push(SlingshotInput {
+					pointer: MOUSE_POINTER,
+					action: SlingshotAction::Press,
+					position: transform.translation.truncate(),
+				});
+			}
+			return;
+		}
+	};
+	let (transform, Velocity(velocity), Radius(radius)) = match asteroids.get(asteroid) {
+		Ok((_, transform, velocity, radius)) => (transform, velocity, radius),
+		Err(_) => {
+			fling.over = true;
+			return;
+		}
+	};
+	let position = transform.translation.truncate();
+
+	if fling.predicted.is_empty() {
+		if slingshots.iter().next().is_some() {
+			// As the trajectories drawn while aiming
+			let mut attractors = MovingAttractors::new(attracting.iter(), &centers);
+			let other_asteroids = asteroid_gravity(
+				&config,
+				asteroids
+					.iter()
+					.map(|(entity, transform, _, Radius(radius))| {
+						(entity, transform.translation.truncate(), *radius)
+					}),
+			);
+			other_asteroids.pulling(asteroid, position, &mut attractors.attractors);
+			let body = BodyState {
+				position,
+				velocity: *velocity,
+			};
+			let aim = position + Vec2::new(150., -100.);
+			fling.predicted =
+				predict_release(&config, body, *radius, aim, &attractors, clock.delta());
+			inputs.0.push(SlingshotInput {
+				pointer: MOUSE_POINTER,
+				action: SlingshotAction::Release,
+				position: aim,
+			});
+		}
+	} else if fling.actual.len() < fling.predicted.len() {
+		fling.actual.push(position);
+	} else {
+		fling.over = true;
+	}
+}
+
+/// Flings the first asteroid with the simulation running at `step` seconds per fixed step, around
+/// a planet with the given moons, and checks that it follows the predicted trajectory.
+fn assert_prediction_matches_the_motion(step: f64, moons: Vec<MoonDescription>) {
+	let mut app = headless_game(SimulationPlugin {
+		seed: Some(42),
+		..Default::default()
+	});
+	app.resources.get_mut::<SimulationClock>().unwrap().step = step;
+	app.resources
+		.get_mut::<Levels>()
+		.unwrap()
+		.current_mut()
+		.planets[0]
+		.moons = moons;
+	app.resources.insert(Fling::default());
+	app.schedule
+		.add_system_to_stage(FIXED_UPDATE, fling_first_asteroid.system());
+	run_until(
+		&mut app,
+		|app| app.resources.get::<Fling>().unwrap().over,
+		|_| {},
+	);
+
+	let fling = app.resources.get::<Fling>().unwrap();
+	assert!(
+		fling.actual.len() > 60,
+		"the asteroid was flung for too short"
+	);
+	assert_eq!(fling.actual, fling.predicted[..fling.actual.len()]);
+}
+
+#[test]
+fn trajectory_prediction_matches_the_motion() {
+	assert_prediction_matches_the_motion(DELTA, vec![]);
+}
+
+#[test]
+fn trajectory_prediction_matches_the_motion_at_another_step() {
+	assert_prediction_matches_the_motion(DELTA / 2., vec![]);
+}
+
+#[test]
+fn trajectory_prediction_follows_orbiting_moons() {
+	// Heavy and fast enough to pull the asteroid differently wherever it is on its orbit
+	let moon = MoonDescription {
+		distance: 250.,
+		radius: 20.,
+		mass: Some(0.5),
+		period: 8.,
+	};
+	assert_prediction_matches_the_motion(DELTA, vec![moon]);
+}